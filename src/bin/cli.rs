@@ -2,7 +2,12 @@ use anyhow::Result;
 use clap::{CommandFactory, Parser, Subcommand};
 use futures_timer::Delay;
 use libp2p::{multiaddr::Protocol, Multiaddr};
-use std::{net::Ipv4Addr, path::PathBuf};
+use std::{
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::task;
 use tracing::{debug, info, trace};
 #[cfg(feature = "tracing-forest")]
@@ -12,13 +17,17 @@ use tracing_subscriber::{
 };
 
 use gra::{
+    chunker::Chunker,
     common::generate_identity,
     daemon::Daemon,
     hash::{Hash, HashOpts},
     models::Models,
     node::{Client, Node},
     reader,
-    storage::Tier,
+    storage::{
+        DataStore, DataStoreError, Denylist, Keyring, ListOptions, Pins, Quota, Quotas, Tier,
+        TierConfig,
+    },
 };
 
 #[cfg(not(feature = "tracing-forest"))]
@@ -27,6 +36,12 @@ use tracing_subscriber::fmt::format::FmtSpan;
 pub const NUM_BUFFERS: usize = std::mem::size_of::<usize>(); // * 8 / BLOCK_SIZE;
 pub const BRANCHING_FACTOR: usize = 2;
 
+/// How often expired values are removed, while the node runs.
+const SWEEP_PERIOD: Duration = Duration::from_secs(60);
+
+/// How often the stores are read back, and corrupt values repaired, while the node runs.
+const SCRUB_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
 // const BOOTSTRAP_NODES: [&str; 1] = ["/dnsaddr/nanoly.cloud"];
 /// This is seed=1
 const BOOTSTRAP_NODES: [&str; 1] = ["12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN"];
@@ -93,6 +108,27 @@ struct Cli {
     #[arg(long)]
     seed: Vec<u8>,
 
+    /// The denylist of forbidden hashes, defaults to ~/.gra/denylist
+    #[arg(long)]
+    denylist: Option<PathBuf>,
+
+    /// The pinned entries, kept by garbage collection, defaults to ~/.gra/pins
+    #[arg(long)]
+    pins: Option<PathBuf>,
+
+    /// The secrets of the scopes to read, and write, one per line, defaults to ~/.gra/keys
+    #[arg(long)]
+    keys: Option<PathBuf>,
+
+    /// Limit a scope, as SCOPE=BYTES, or SCOPE=BYTES,OBJECTS, repeated for each scope
+    #[arg(long)]
+    quota: Vec<String>,
+
+    /// The ordered tiers to store values in, defaults to ~/.gra/tiers.toml, or to memory, and
+    /// the network, when that does not exist
+    #[arg(long)]
+    tiers: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 
@@ -105,8 +141,16 @@ pub enum Commands {
     Add {
         /// The path to add
         path: PathBuf,
-        /// The scope to use for the hash,
-        scope: Option<String>,
+        /// The scope to add under, by its hash, whose secret must be in the keyfile
+        scope: Option<Hash>,
+        /// Read the secret of the scope to add under from stdin, appending it to the keyfile, so
+        /// it never shows in the process list, or the shell history
+        #[arg(long, conflicts_with = "scope")]
+        secret_stdin: bool,
+        /// Split files by their content, into leaf blocks of MIN to MAX bytes, AVG on average,
+        /// rather than into a single block, as MIN:AVG:MAX, defaulting to 2048:8192:65536
+        #[arg(long, num_args = 0..=1, default_missing_value = "2048:8192:65536")]
+        chunker: Option<Chunker>,
     },
     /// Execute a data stream
     Run {
@@ -115,13 +159,27 @@ pub enum Commands {
     },
     /// Query a hash
     Query {
-        /// The file to query
-        input: String,
+        /// The hash to query, as `hash`, or `scope|hash`
+        hash: Hash,
     },
     /// Pay respect. Mark and share the file
     F {
         input: String,
     },
+    /// List stored entries, and the tiers holding them
+    List {
+        /// Only list keys starting with this prefix
+        prefix: Option<String>,
+        /// Only list keys within this scope, given as its hash
+        #[arg(long, conflicts_with = "prefix")]
+        scope: Option<Hash>,
+        /// Continue from after this key
+        #[arg(long)]
+        after: Option<String>,
+        /// The maximum number of keys to list
+        #[arg(long)]
+        limit: Option<usize>,
+    },
     /// Show the status of the node
     Status,
     /// Configure the system
@@ -137,6 +195,57 @@ pub enum Commands {
     /// Start as a daemon
     Daemon {},
     Listen {},
+    /// Prevent storage, and transmission of a hash
+    Forbid {
+        /// The hash to forbid
+        hash: Hash,
+    },
+    /// Re-enable storage, and transmission of a hash
+    Allow {
+        /// The hash to allow
+        hash: Hash,
+    },
+    /// Keep an entry, and every block it reaches, through garbage collection
+    Pin {
+        /// The entry to pin, as `hash`, or `scope|hash`
+        hash: Hash,
+    },
+    /// Release a pinned entry
+    Unpin {
+        /// The entry to unpin, as `hash`, or `scope|hash`
+        hash: Hash,
+    },
+    /// Delete every block unreachable from a pinned entry
+    Gc {
+        /// Report what would be deleted, without deleting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show the objects, and bytes, held by each tier, and each scope
+    Usage {},
+    /// Remove every expired value
+    Sweep {},
+    /// Re-hash every stored value, quarantining, and repairing, any that are corrupt
+    Scrub {},
+    /// Manage the denylist of forbidden hashes
+    Denylist {
+        #[command(subcommand)]
+        action: DenylistAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DenylistAction {
+    /// Forbid every hash listed in a file, one per line
+    Import {
+        /// The file to import
+        path: PathBuf,
+    },
+    /// Write every forbidden hash to a file
+    Export {
+        /// The file to export to
+        path: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
@@ -177,13 +286,22 @@ async fn main() -> Result<()> {
     let daemon_address = Multiaddr::from(opts.daemon_address);
     info!("Daemon Address: {:?}", daemon_address);
 
-    let models = Models::new(Some(vec![Tier::Memory]));
-
     let mut seed = opts.seed.clone();
     let seed = seed.as_mut_slice();
 
     let identity = generate_identity(Some(seed));
 
+    let denylist = Arc::new(Denylist::open(
+        opts.denylist
+            .clone()
+            .unwrap_or_else(|| Denylist::default_path("denylist")),
+    )?);
+    let pins = Arc::new(Pins::open(
+        opts.pins
+            .clone()
+            .unwrap_or_else(|| Pins::default_path("pins")),
+    )?);
+
     if let Some(Commands::Daemon {}) = &opts.command {
         info!("Starting Daemon");
         let daemon = Daemon::new(address.to_owned(), identity)?;
@@ -195,31 +313,97 @@ async fn main() -> Result<()> {
         identity,
         Some(daemon_address.to_owned()),
         Some(BOOTSTRAP_NODES),
+        denylist.clone(),
     )?;
 
     let mut client = node.client();
 
+    let quotas = Arc::new(Quotas::default());
+    for quota in &opts.quota {
+        let Some((scope, quota)) = quota.split_once('=') else {
+            return Err(DataStoreError::Invalid.into());
+        };
+        quotas.set(&Hash::new(scope.as_bytes(), None), Quota::from_str(quota)?);
+    }
+
+    let tiers = match &opts.tiers {
+        Some(path) => TierConfig::load(path, Some(&client))?,
+        None if TierConfig::default_path().exists() => {
+            TierConfig::load(&TierConfig::default_path(), Some(&client))?
+        }
+        None => vec![Tier::Process.into(), Tier::Remote(client.clone()).into()],
+    };
+    let keys = opts.keys.clone().unwrap_or_else(Keyring::default_path);
+    let mut models = Models::new(
+        Some(tiers),
+        denylist,
+        pins,
+        Arc::new(Keyring::load(&keys)?),
+        quotas,
+    )?;
+    models.recover().await?;
+    models.recount().await?;
+    let changes = models.blocks().on_change().await?;
+    let mut announcer = client.clone();
+    task::spawn(async move { announcer.announce(changes).await });
+
+    let events = node.events();
     let handle = task::spawn(async move { node.run().await });
 
     /// Wait for the node to start, this is a hack to help me debug libp2p startup
     Delay::new(std::time::Duration::from_secs(5)).await;
     let Cli { command, input, .. } = opts;
-    let _ = command_handler(&mut client, command, input, address).await;
-
+    let _ = command_handler(&mut client, &mut models, command, input, address, &keys).await;
+
+    // Requests arriving while the command runs queue up, until the node refuses more.
+    // The stores are swept, and scrubbed, for as long as requests are served.
+    if let Some(events) = events {
+        let serve = models.serve(events, client);
+        let maintain = futures::future::join(
+            models.sweep_every(SWEEP_PERIOD),
+            models.scrub_every(SCRUB_PERIOD),
+        );
+        futures::pin_mut!(serve, maintain);
+        futures::future::select(serve, maintain).await;
+    }
     handle.await?;
     Ok(())
 }
 
 async fn command_handler(
     client: &mut Client,
+    models: &mut Models,
     command: Option<Commands>,
     input: Option<String>,
     address: Multiaddr,
+    keys: &Path,
 ) -> Result<()> {
     match command {
-        Some(Commands::Add { path, scope }) => {
+        Some(Commands::Add {
+            path,
+            scope,
+            secret_stdin,
+            chunker,
+        }) => {
             debug!("Adding {:?}", path);
-            let scope = scope.map(|scope| Hash::new(scope.as_bytes(), None));
+            // Blocks of a scope are encrypted with a key derived from its secret.
+            let scope = if secret_stdin {
+                let mut secret = String::new();
+                std::io::stdin().read_line(&mut secret)?;
+                Some(
+                    models
+                        .keyring()
+                        .add(keys, secret.trim_end_matches(['\r', '\n']))?,
+                )
+            } else {
+                if scope
+                    .as_ref()
+                    .is_some_and(|scope| !models.keyring().contains(scope))
+                {
+                    return Err(DataStoreError::PermissionDenied.into());
+                }
+                scope
+            };
             let hash = Hash::new(
                 path.to_string_lossy().as_bytes(),
                 Some(HashOpts {
@@ -228,20 +412,43 @@ async fn command_handler(
             );
 
             trace!("Path hash: {:?}", hash);
-            reader::add_path(&path, scope.to_owned())?;
+            models
+                .commit(reader::stage_path(
+                    &path,
+                    scope.to_owned(),
+                    chunker,
+                    models.keyring(),
+                )?)
+                .await?;
 
             client.start_providing(hash).await;
 
             Ok(())
         }
-        Some(Commands::Query { input }) => {
-            debug!("Querying for {:?}", input);
-            let hash = Hash::new(input.as_bytes(), None);
-            trace!("Hash: {:?}", hash);
+        Some(Commands::Query { hash }) => {
+            debug!("Querying for {:?}", hash);
             let block = client.request_block(hash, None).await?;
             debug!("Result {:?}", block);
             Ok(())
         }
+        Some(Commands::List {
+            prefix,
+            scope,
+            after,
+            limit,
+        }) => {
+            let mut options = match (prefix, scope) {
+                (Some(prefix), _) => ListOptions::prefix(&prefix),
+                (_, Some(scope)) => ListOptions::scope(&scope),
+                _ => ListOptions::default(),
+            };
+            options.after = after;
+            options.limit = limit;
+            for listed in models.entries().locate(&options).await? {
+                println!("{} {:?}", listed.key, listed.tiers);
+            }
+            Ok(())
+        }
         Some(Commands::Status) => todo!(),
         Some(Commands::Config { action }) => match action {
             ConfigAction::Set { key, value } => todo!(),
@@ -253,6 +460,81 @@ async fn command_handler(
             Ok(())
         }
         Some(Commands::Daemon {}) => Ok(()),
+        // Blocks are keyed by their hash alone, and entries by their scoped form, though both are
+        // forbidden by the hash alone.
+        Some(Commands::Forbid { hash }) => {
+            models.blocks().forbid(&hash.to_hex()).await?;
+            models.entries().forbid(&hash.to_string()).await
+        }
+        Some(Commands::Allow { hash }) => {
+            models.blocks().allow(&hash.to_hex()).await?;
+            models.entries().allow(&hash.to_string()).await
+        }
+        Some(Commands::Pin { hash }) => models.pin(&hash.to_string()).await,
+        Some(Commands::Unpin { hash }) => models.unpin(&hash.to_string()).await,
+        Some(Commands::Gc { dry_run }) => {
+            let report = models.gc(dry_run).await?;
+            for key in &report.swept {
+                println!("{key}");
+            }
+            for key in &report.unfollowed {
+                println!("unfollowed {key}");
+            }
+            println!(
+                "{} blocks, {} bytes reclaimable, {} reachable, {} sealed in scopes not followed",
+                report.swept.len(),
+                report.reclaimable,
+                report.reachable,
+                report.unopened
+            );
+            Ok(())
+        }
+        Some(Commands::Usage {}) => {
+            for (tier, usage) in models.blocks().usage().await? {
+                println!("{tier}: {} objects, {} bytes", usage.objects, usage.bytes);
+            }
+            for (scope, usage) in models.quotas().usages() {
+                let scope = if scope.is_empty() { "-" } else { &scope };
+                println!("{scope}: {} objects, {} bytes", usage.objects, usage.bytes);
+            }
+            Ok(())
+        }
+        Some(Commands::Sweep {}) => {
+            for key in models.sweep().await? {
+                println!("{key}");
+            }
+            Ok(())
+        }
+        Some(Commands::Scrub {}) => {
+            let report = models.scrub().await?;
+            for key in &report.repaired {
+                println!("repaired {key}");
+            }
+            for key in &report.unrepaired {
+                println!("unrepaired {key}");
+            }
+            for key in &report.skipped {
+                println!("skipped {key}");
+            }
+            println!(
+                "{} checked, {} quarantined, {} repaired, {} unrepaired, {} skipped",
+                report.checked,
+                report.quarantined.len(),
+                report.repaired.len(),
+                report.unrepaired.len(),
+                report.skipped.len()
+            );
+            Ok(())
+        }
+        Some(Commands::Denylist { action }) => match action {
+            DenylistAction::Import { path } => {
+                let imported = models.denylist().import(&path)?;
+                let purged = models.purge().await?;
+                info!("Forbade {imported} hashes from {path:?}, deleting {purged} stored values");
+                Ok(())
+            }
+            DenylistAction::Export { path } => models.denylist().export(&path),
+        },
         Some(cmd) => {
             if let Some(input) = &input {
                 // let result = node.get(&Hash::new(input.as_bytes(), None))?;
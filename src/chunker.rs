@@ -0,0 +1,222 @@
+use anyhow::{bail, Result};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use crate::storage::DataStoreError;
+
+/// The gear hash value of each byte, generated with splitmix64 from a fixed seed.
+///
+/// Every node must cut the same content at the same points for it to deduplicate, so the table
+/// is as much a part of the format as the sizes are, and must never change.
+const GEAR: [u64; 256] = gear(0x6772_6100);
+
+const fn gear(seed: u64) -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state = seed;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits content at points chosen by the content itself, with FastCDC, so that an edit only
+/// changes the pieces around it, and the rest still deduplicate.
+///
+/// Written, and parsed, as `MIN:AVG:MAX`, in bytes.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "Sizes")]
+pub struct Chunker {
+    min: u32,
+    avg: u32,
+    max: u32,
+}
+
+/// A chunker as decoded, before its sizes are checked.
+#[derive(serde::Deserialize)]
+struct Sizes {
+    min: u32,
+    avg: u32,
+    max: u32,
+}
+
+impl TryFrom<Sizes> for Chunker {
+    type Error = DataStoreError;
+
+    /// Fails with `Invalid` unless `64 <= min <= avg <= max`, as `cut` relies on.
+    fn try_from(Sizes { min, avg, max }: Sizes) -> Result<Self, Self::Error> {
+        if !Self::is_valid(min, avg, max) {
+            return Err(DataStoreError::Invalid);
+        }
+        Ok(Chunker { min, avg, max })
+    }
+}
+
+impl Chunker {
+    /// The sizes of the FastCDC paper, pieces of 2 KiB to 64 KiB, 8 KiB on average.
+    pub const DEFAULT: Chunker = Chunker {
+        min: 2048,
+        avg: 8192,
+        max: 65536,
+    };
+
+    /// Fails with `Invalid` unless `64 <= min <= avg <= max`.
+    pub fn new(min: u32, avg: u32, max: u32) -> Result<Self> {
+        if !Self::is_valid(min, avg, max) {
+            bail!(DataStoreError::Invalid);
+        }
+        Ok(Chunker { min, avg, max })
+    }
+
+    fn is_valid(min: u32, avg: u32, max: u32) -> bool {
+        64 <= min && min <= avg && avg <= max
+    }
+
+    pub fn min(&self) -> u32 {
+        self.min
+    }
+
+    pub fn avg(&self) -> u32 {
+        self.avg
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// Splits the content into pieces of `min` to `max` bytes, apart from the last, which may be
+    /// shorter.
+    pub fn split<'a>(&self, content: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut pieces = Vec::new();
+        let mut rest = content;
+        while !rest.is_empty() {
+            let (piece, tail) = rest.split_at(self.cut(rest));
+            pieces.push(piece);
+            rest = tail;
+        }
+        pieces
+    }
+
+    /// The length of the next piece, i.e. the first point past `min` at which the gear hash of
+    /// the bytes before it has none of the masked bits set.
+    ///
+    /// The mask is harder to match before `avg`, and easier after it, so the pieces cluster around
+    /// `avg`. Only the top bits of the hash are masked, as those depend on the most bytes.
+    fn cut(&self, content: &[u8]) -> usize {
+        let (min, avg, max) = (self.min as usize, self.avg as usize, self.max as usize);
+        if content.len() <= min {
+            return content.len();
+        }
+        let bits = self.avg.ilog2();
+        let (hard, easy) = (mask(bits + 1), mask(bits - 1));
+        let normal = content.len().min(avg);
+        let end = content.len().min(max);
+
+        let mut hash: u64 = 0;
+        for (i, byte) in content.iter().enumerate().take(end).skip(min) {
+            hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+            let mask = if i < normal { hard } else { easy };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+/// The top `bits` bits of a hash.
+fn mask(bits: u32) -> u64 {
+    u64::MAX << (64 - bits)
+}
+
+impl Default for Chunker {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Display for Chunker {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.min, self.avg, self.max)
+    }
+}
+
+impl FromStr for Chunker {
+    type Err = DataStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sizes = s
+            .split(':')
+            .map(|size| size.parse::<u32>().map_err(|_| DataStoreError::Invalid))
+            .collect::<Result<Vec<_>, _>>()?;
+        match sizes[..] {
+            [min, avg, max] if Self::is_valid(min, avg, max) => Ok(Chunker { min, avg, max }),
+            _ => Err(DataStoreError::Invalid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes which look random, but are the same on every run.
+    fn content(len: usize) -> Vec<u8> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_is_deterministic() {
+        let content = content(1 << 20);
+        let chunker = Chunker::new(256, 1024, 4096).unwrap();
+        assert_eq!(chunker.split(&content), chunker.split(&content));
+        assert_eq!(chunker.split(&content).concat(), content);
+    }
+
+    #[test]
+    fn pieces_are_within_min_and_max() {
+        let content = content(1 << 20);
+        let chunker = Chunker::new(256, 1024, 4096).unwrap();
+        let pieces = chunker.split(&content);
+        let (last, rest) = pieces.split_last().unwrap();
+        assert!(rest.iter().all(|piece| (256..=4096).contains(&piece.len())));
+        assert!((1..=4096).contains(&last.len()));
+    }
+
+    #[test]
+    fn insertion_only_changes_nearby_pieces() {
+        let content = content(1 << 20);
+        let mut edited = content.clone();
+        edited.splice(500_000..500_000, *b"inserted");
+        let chunker = Chunker::new(256, 1024, 4096).unwrap();
+        let before = chunker.split(&content);
+        let after = chunker.split(&edited);
+        let changed = after.iter().filter(|piece| !before.contains(piece)).count();
+        assert!(0 < changed && changed <= 3, "{changed} pieces changed");
+    }
+
+    #[test]
+    fn deserialize_rejects_invalid_sizes() {
+        let valid: Chunker = serde_json::from_str(r#"{"min":64,"avg":128,"max":256}"#).unwrap();
+        assert_eq!(valid, Chunker::new(64, 128, 256).unwrap());
+        assert!(serde_json::from_str::<Chunker>(r#"{"min":64,"avg":0,"max":256}"#).is_err());
+        assert!(serde_json::from_str::<Chunker>(r#"{"min":0,"avg":0,"max":0}"#).is_err());
+    }
+}
@@ -93,8 +93,10 @@ pub async fn handle<B: NetworkBehaviour>(swarm: &mut Swarm<B>, event: &kad::Even
                 error!("Failed to put record: {err:?}");
             }
             kad::QueryResult::StartProviding(Ok(kad::AddProviderOk { key })) => {
-                let hash = Hash::from(key.clone());
-                info!("Successfully put provider record {hash:?}",);
+                match Hash::try_from(key.clone()) {
+                    Ok(hash) => info!("Successfully put provider record {hash:?}"),
+                    Err(e) => warn!("Put a provider record, under an undecodable key: {e}"),
+                }
             }
             kad::QueryResult::StartProviding(Err(err)) => {
                 error!("Failed to put provider record: {err:?}");
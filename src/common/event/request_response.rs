@@ -15,16 +15,10 @@ pub async fn handle<B: NetworkBehaviour>(
 ) {
     match event {
         request_response::Event::Message { message, .. } => match message {
-            request_response::Message::Request {
-                request, channel, ..
-            } => {
-                // self.event_sender
-                //     .send(Event::InboundRequest {
-                //         request: request.into(),
-                //         channel,
-                //     })
-                //     .await
-                //     .expect("Event receiver not to be dropped.");
+            // Nodes take their requests before they reach here, to serve them from their models.
+            // Anything else holds no blocks, so lets the channel drop, refusing the request.
+            request_response::Message::Request { request, .. } => {
+                trace!("Not serving {:?}", request.inner());
             }
             request_response::Message::Response {
                 request_id,
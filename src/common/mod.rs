@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
@@ -34,12 +35,26 @@ pub mod command;
 pub mod event;
 
 use crate::hash::Hash;
-use crate::models::{Block, BLOCK_SIZE};
+use crate::models::{Block, Slice, BLOCK_SIZE};
 
 pub const BUF_SIZE: usize = BLOCK_SIZE + 10;
 
+/// Revision of the block request and response messages, raised whenever their
+/// encoding changes so that peers speaking an older one are not matched.
+///
+/// Only the current revision is registered, so a node can't exchange blocks with
+/// one of another revision, and nodes must be upgraded together. Revision 2
+/// answers with either a whole block, or a verified slice of its content, where
+/// the first, unnumbered, revision always answered with the whole block.
+pub const MESSAGE_VERSION: u32 = 2;
+
 lazy_static! {
-    pub static ref PROTOCOL: String = format!("/{}/{}", crate_name!(), crate_version!());
+    pub static ref PROTOCOL: String = format!(
+        "/{}/{}/{}",
+        crate_name!(),
+        crate_version!(),
+        MESSAGE_VERSION
+    );
 }
 
 #[repr(C)]
@@ -55,11 +70,15 @@ pub enum Event {
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct BlockRequest {
     pub hash: Hash,
+    /// The bytes of the canonical encoding of the block asked for, as a verifiable slice, or the
+    /// whole block, if unset.
+    #[serde(default)]
+    pub range: Option<Range<u64>>,
 }
 
 impl BlockRequest {
     pub fn new(hash: Hash) -> Self {
-        Self { hash }
+        Self { hash, range: None }
     }
 
     pub fn inner(&self) -> &Hash {
@@ -69,17 +88,16 @@ impl BlockRequest {
 
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-pub struct BlockResponse {
-    block: Block,
+pub enum BlockResponse {
+    /// The whole block, when no range is asked for, or it has no content to slice.
+    Block(Block),
+    /// A range of the content of a `Block::Bytes`, along with its proof.
+    Slice(Slice),
 }
 
 impl BlockResponse {
     pub fn new(block: Block) -> Self {
-        Self { block }
-    }
-
-    pub fn inner(&self) -> &Block {
-        &self.block
+        Self::Block(block)
     }
 }
 
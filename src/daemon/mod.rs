@@ -1,12 +1,10 @@
 use anyhow::{anyhow, bail, Result};
 use async_std::task;
-use clap::{crate_name, crate_version};
 use futures::channel::mpsc::{self, Receiver, Sender};
 use futures::channel::oneshot;
 use futures::prelude::*;
 use futures::{FutureExt, StreamExt};
 use hashbrown::{hash_map, HashMap};
-use lazy_static::lazy_static;
 use libp2p::identity::Keypair;
 use libp2p::kad::{Record, RecordKey};
 use libp2p::multiaddr::Protocol;
@@ -33,10 +31,6 @@ use behaviour::{Behaviour, BehaviourEvent};
 
 pub mod event;
 
-lazy_static! {
-    pub static ref PROTOCOL: String = format!("/gra/{}/{}", crate_name!(), crate_version!());
-}
-
 #[repr(C)]
 pub struct Daemon {
     address: Multiaddr,
@@ -1,11 +1,21 @@
-use std::fmt::{self, Debug, Display, Formatter};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
+};
 
+use anyhow::Result;
 use blake3::{Hash as B3Hash, OUT_LEN};
 use ciborium::{cbor, into_writer};
+use derive_more::Display;
 use libp2p::{kad::RecordKey, request_response::cbor};
+use multibase::Base;
+use multihash::Multihash;
+use thiserror::Error;
 use tracing::info;
 use zerocopy::AsBytes;
 
+use crate::storage::DataStoreError;
+
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct HashOpts {
     pub key: Option<Hash>,
@@ -15,7 +25,46 @@ pub struct HashOpts {
 #[derive(Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Hash(B3Hash, Option<Box<Hash>>);
 
-/// Copies the bytes of the key into a new vector.
+/// Separates a hash from the scope it was keyed with, in its textual form.
+const SCOPE_SEPARATOR: char = '|';
+
+/// The multicodec code of a blake3 digest.
+pub const BLAKE3_CODE: u64 = 0x1e;
+
+/// The version, and content type, a CID of a hash is written with. A hash covers raw bytes.
+const CID_VERSION: u8 = 0x01;
+const RAW_CODEC: u8 = 0x55;
+
+/// The format of the DHT key derived from a hash.
+///
+/// Provider records are published under every version, and looked up under each in turn, newest
+/// first, so the format can change without stranding records published by older nodes. There is
+/// no conversion into a `RecordKey`; callers name the version they mean.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyVersion {
+    /// The CBOR of the hash, as published by older nodes.
+    Cbor,
+    /// The multihash of the hash, leaving out its scope.
+    Multihash,
+}
+
+impl KeyVersion {
+    pub const LATEST: KeyVersion = KeyVersion::Multihash;
+
+    /// Every version, newest first.
+    pub const ALL: [KeyVersion; 2] = [KeyVersion::Multihash, KeyVersion::Cbor];
+}
+
+/// Why text failed to parse as a `Hash`.
+#[derive(Debug, Display, Error, Clone, Copy, PartialEq, Eq)]
+pub enum ParseHashError {
+    #[display(fmt = "empty hash")]
+    Empty,
+    /// The hash, or one of its scopes, numbered from the outermost scope, is neither 64 hex
+    /// characters, nor a CID.
+    #[display(fmt = "part {} of the hash is neither hex, nor a CID", _0)]
+    InvalidPart(usize),
+}
 
 impl Hash {
     pub fn new(data: &[u8], opts: Option<HashOpts>) -> Self {
@@ -45,9 +94,59 @@ impl Hash {
         // v.into_bytes().expect("Failed to convert Hash to bytes")
     }
 
-    /// Create a `Hash` from its cbor representation.
-    pub fn from_cbor(bytes: Vec<u8>) -> Self {
-        ciborium::de::from_reader(bytes.as_slice()).expect("Failed to deserialize Hash")
+    /// Create a `Hash` from its cbor representation, failing with `Decode` if it isn't one.
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        Ok(ciborium::de::from_reader(bytes).map_err(|_| DataStoreError::Decode)?)
+    }
+
+    /// The hash this one was keyed with, i.e. its scope.
+    pub fn key(&self) -> Option<&Hash> {
+        self.1.as_deref()
+    }
+
+    /// The self-describing multihash of the hash, leaving out its scope.
+    pub fn to_multihash(&self) -> Multihash<64> {
+        Multihash::wrap(BLAKE3_CODE, self.as_bytes()).expect("a blake3 digest to fit")
+    }
+
+    /// Creates an unscoped `Hash` from a multihash, failing with `Decode` if it isn't blake3.
+    pub fn from_multihash(multihash: &Multihash<64>) -> Result<Self> {
+        let bytes: [u8; OUT_LEN] = multihash
+            .digest()
+            .try_into()
+            .ok()
+            .filter(|_| multihash.code() == BLAKE3_CODE)
+            .ok_or(DataStoreError::Decode)?;
+        Ok(Hash(B3Hash::from_bytes(bytes), None))
+    }
+
+    /// The CIDv1 of the hash, in base32, e.g. `bafkr4i...`, with its scope before it, as in the
+    /// hex form, so it round-trips through `from_str`.
+    pub fn to_cid(&self) -> String {
+        let mut cid = vec![CID_VERSION, RAW_CODEC];
+        cid.extend_from_slice(&self.to_multihash().to_bytes());
+        let cid = multibase::encode(Base::Base32Lower, cid);
+        match &self.1 {
+            Some(key) => format!("{}{SCOPE_SEPARATOR}{cid}", key.to_cid()),
+            None => cid,
+        }
+    }
+
+    /// Parses a single CID, without a scope, failing with `Decode` if it isn't a CID of a hash.
+    fn from_cid(s: &str) -> Result<Self> {
+        let (_, bytes) = multibase::decode(s).map_err(|_| DataStoreError::Decode)?;
+        let [CID_VERSION, RAW_CODEC, multihash @ ..] = bytes.as_slice() else {
+            return Err(DataStoreError::Decode.into());
+        };
+        Self::from_multihash(&Multihash::from_bytes(multihash).map_err(|_| DataStoreError::Decode)?)
+    }
+
+    /// The DHT key of the hash, in the given format.
+    pub fn record_key(&self, version: KeyVersion) -> RecordKey {
+        match version {
+            KeyVersion::Cbor => RecordKey::from(self.to_cbor()),
+            KeyVersion::Multihash => RecordKey::from(self.to_multihash().to_bytes()),
+        }
     }
 
     pub fn to_hex(&self) -> String {
@@ -65,10 +164,12 @@ impl Hash {
     }
 }
 
+/// Formatted as `hash`, or as `scope|hash` when keyed, with the scope formatted the same way, so
+/// a chain of scopes reads from the outermost, e.g. `outer|inner|hash`.
 impl Display for Hash {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         if let Some(key) = &self.1 {
-            write!(f, "{}|{}", key.to_hex(), self.to_hex())
+            write!(f, "{key}{SCOPE_SEPARATOR}{}", self.to_hex())
         } else {
             write!(f, "{}", self.to_hex())
         }
@@ -77,11 +178,40 @@ impl Display for Hash {
 
 impl Debug for Hash {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        if let Some(key) = &self.1 {
-            write!(f, "{}|{}", key.to_hex(), self.to_hex())
-        } else {
-            write!(f, "{}", self.to_hex())
+        Display::fmt(self, f)
+    }
+}
+
+/// Parses the textual form written by `Display`, or by `to_cid`, or a mix of the two.
+impl FromStr for Hash {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParseHashError::Empty);
         }
+        s.split(SCOPE_SEPARATOR)
+            .enumerate()
+            .try_fold(None, |key, (part, text)| {
+                let hash = match B3Hash::from_hex(text) {
+                    Ok(hash) => hash,
+                    Err(_) => {
+                        Self::from_cid(text)
+                            .map_err(|_| ParseHashError::InvalidPart(part))?
+                            .0
+                    }
+                };
+                Ok(Some(Hash(hash, key.map(Box::new))))
+            })
+            .map(|hash| hash.expect("split to yield at least one part"))
+    }
+}
+
+impl TryFrom<&str> for Hash {
+    type Error = ParseHashError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
@@ -97,15 +227,16 @@ impl From<B3Hash> for Hash {
     }
 }
 
-impl Into<RecordKey> for Hash {
-    fn into(self) -> RecordKey {
-        RecordKey::from(self.to_cbor())
-    }
-}
+/// Parses a DHT key of any version. A multihash always starts with the blake3 code, and CBOR
+/// with the head of a two element array, so the two are told apart.
+impl TryFrom<RecordKey> for Hash {
+    type Error = anyhow::Error;
 
-impl From<RecordKey> for Hash {
-    fn from(key: RecordKey) -> Self {
-        Self::from_cbor(key.to_vec())
+    fn try_from(key: RecordKey) -> Result<Self> {
+        match Multihash::from_bytes(key.as_ref()) {
+            Ok(multihash) if multihash.code() == BLAKE3_CODE => Self::from_multihash(&multihash),
+            _ => Self::from_cbor(key.as_ref()),
+        }
     }
 }
 
@@ -115,9 +246,11 @@ impl Into<Vec<u8>> for Hash {
     }
 }
 
-impl From<&[u8]> for Hash {
-    fn from(value: &[u8]) -> Hash {
-        Hash::from_cbor(value.to_vec())
+impl TryFrom<&[u8]> for Hash {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Hash::from_cbor(value)
     }
 }
 
@@ -136,3 +269,114 @@ impl PartialOrd for Hash {
 pub trait CustomHash {
     fn hash(&self) -> [u8; OUT_LEN];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scoped(data: &[u8], scope: Hash) -> Hash {
+        Hash::new(data, Some(HashOpts { key: Some(scope) }))
+    }
+
+    #[test]
+    fn display_form_round_trips() {
+        let hash = Hash::new(b"data", None);
+        assert_eq!(hash.to_string(), hash.to_hex());
+        assert_eq!(hash.to_string().parse::<Hash>().unwrap(), hash);
+
+        let scope = Hash::new(b"scope", None);
+        let hash = scoped(b"data", scope.clone());
+        assert_eq!(
+            hash.to_string(),
+            format!("{}|{}", scope.to_hex(), hash.to_hex())
+        );
+        assert_eq!(hash.to_string().parse::<Hash>().unwrap(), hash);
+    }
+
+    #[test]
+    fn nested_scopes_read_from_the_outermost() {
+        let outer = Hash::new(b"outer", None);
+        let inner = scoped(b"inner", outer.clone());
+        let hash = scoped(b"data", inner.clone());
+        assert_eq!(
+            hash.to_string(),
+            format!("{}|{}|{}", outer.to_hex(), inner.to_hex(), hash.to_hex())
+        );
+
+        let parsed: Hash = hash.to_string().parse().unwrap();
+        assert_eq!(parsed, hash);
+        assert_eq!(parsed.key(), Some(&inner));
+        assert_eq!(parsed.key().and_then(Hash::key), Some(&outer));
+    }
+
+    #[test]
+    fn malformed_text_names_the_bad_part() {
+        let hex = Hash::new(b"data", None).to_hex();
+        assert_eq!("".parse::<Hash>(), Err(ParseHashError::Empty));
+        assert_eq!("zz".parse::<Hash>(), Err(ParseHashError::InvalidPart(0)));
+        assert_eq!(
+            hex[1..].parse::<Hash>(),
+            Err(ParseHashError::InvalidPart(0))
+        );
+        assert_eq!(
+            format!("{hex}|").parse::<Hash>(),
+            Err(ParseHashError::InvalidPart(1))
+        );
+        assert_eq!(
+            format!("{hex}|{hex}|not a hash").parse::<Hash>(),
+            Err(ParseHashError::InvalidPart(2))
+        );
+    }
+
+    #[test]
+    fn cid_round_trips() {
+        let hash = Hash::new(b"data", None);
+        let cid = hash.to_cid();
+        assert!(cid.starts_with("bafkr4i"));
+        assert_eq!(Hash::from_cid(&cid).unwrap(), hash);
+        assert_eq!(cid.parse::<Hash>().unwrap(), hash);
+
+        let hash = scoped(b"data", scoped(b"inner", Hash::new(b"outer", None)));
+        assert_eq!(hash.to_cid().parse::<Hash>().unwrap(), hash);
+        // Each part may be written either way.
+        let mixed = format!("{}|{}", hash.key().unwrap(), Hash::new(b"", None).to_cid());
+        let parsed: Hash = mixed.parse().unwrap();
+        assert_eq!(parsed.key(), hash.key());
+        assert_eq!(parsed.as_bytes(), Hash::new(b"", None).as_bytes());
+    }
+
+    #[test]
+    fn malformed_cids_are_rejected() {
+        let cid = |bytes: Vec<u8>| multibase::encode(Base::Base32Lower, bytes);
+        let digest = *Hash::new(b"data", None).as_bytes();
+        let sha256 = Multihash::<64>::wrap(0x12, &digest).unwrap().to_bytes();
+        let blake3 = Hash::new(b"data", None).to_multihash().to_bytes();
+
+        assert!(Hash::from_cid("not a cid").is_err());
+        assert!(Hash::from_cid(&cid([vec![CID_VERSION, RAW_CODEC], sha256].concat())).is_err());
+        assert!(Hash::from_cid(&cid([vec![CID_VERSION, 0x71], blake3.clone()].concat())).is_err());
+        assert!(Hash::from_cid(&cid([vec![CID_VERSION, RAW_CODEC], blake3].concat())).is_ok());
+        assert_eq!(
+            cid(vec![CID_VERSION]).parse::<Hash>(),
+            Err(ParseHashError::InvalidPart(0))
+        );
+    }
+
+    #[test]
+    fn record_keys_round_trip() {
+        let hash = scoped(b"data", Hash::new(b"scope", None));
+
+        // CBOR carries the scope, while a multihash leaves it out.
+        let cbor = Hash::try_from(hash.record_key(KeyVersion::Cbor)).unwrap();
+        assert_eq!(cbor, hash);
+        let multihash = Hash::try_from(hash.record_key(KeyVersion::Multihash)).unwrap();
+        assert_eq!(multihash.as_bytes(), hash.as_bytes());
+        assert_eq!(multihash.key(), None);
+
+        assert_ne!(
+            hash.record_key(KeyVersion::Cbor),
+            hash.record_key(KeyVersion::Multihash)
+        );
+        assert!(Hash::try_from(RecordKey::from(b"garbage".to_vec())).is_err());
+    }
+}
@@ -5,6 +5,8 @@ pub mod node;
 
 pub mod reader;
 
+pub mod chunker;
+
 pub mod common;
 
 pub mod hash;
@@ -0,0 +1,345 @@
+use anyhow::{anyhow, bail, Result};
+use ciborium::{from_reader, into_writer};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+use tracing::{debug, info, warn};
+
+use super::{forbidden_as, Block, Entry, Model, Models};
+use crate::storage::{Change, DataKey, DataStore, DataStoreError, DataType, Expiry, Storage};
+
+/// Blocks, and entries, staged to be written together, all or nothing.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Batch {
+    blocks: Vec<Block>,
+    entries: Vec<Entry>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put_block(&mut self, block: Block) -> &mut Self {
+        self.blocks.push(block);
+        self
+    }
+
+    pub fn put_entry(&mut self, entry: Entry) -> &mut Self {
+        self.entries.push(entry);
+        self
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len() + self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A write-ahead log, holding the batch being committed, kept beside the first persistent tier.
+///
+/// Values are content addressed, so a batch found in the log after a crash is simply written
+/// again in full, rolling it forward.
+#[derive(Debug)]
+pub struct Wal {
+    path: PathBuf,
+}
+
+impl Wal {
+    pub fn new(path: PathBuf) -> Self {
+        Wal { path }
+    }
+
+    /// Logs the batch, durably, before any of it is applied.
+    fn begin(&self, batch: &Batch) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut encoded = Vec::new();
+        into_writer(batch, &mut encoded).map_err(|e| anyhow!("{e}"))?;
+        let staging_path = self.path.with_extension("tmp");
+        let mut file = File::create(&staging_path)?;
+        file.write_all(&encoded)?;
+        file.sync_all()?;
+        fs::rename(staging_path, &self.path)?;
+        Ok(())
+    }
+
+    /// The batch left in the log by an interrupted commit, if any.
+    fn pending(&self) -> Result<Option<Batch>> {
+        match File::open(&self.path) {
+            Ok(file) => Ok(Some(from_reader(file).map_err(|e| anyhow!("{e}"))?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn end(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A value as it was before the batch wrote over it, along with when it expired, so that the
+/// write can be undone.
+struct Undo<T> {
+    key: String,
+    previous: Option<(T, Option<Expiry>)>,
+}
+
+impl<T: DataType> Model<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes every value, recording how to undo each write, and stopping at the first failure.
+    async fn apply(&mut self, values: &[T], undo: &mut Vec<Undo<T>>) -> Result<()> {
+        for value in values {
+            let key = value.key();
+            let previous = self.stored(&key).await;
+            self.write(&key, value).await?;
+            undo.push(Undo { key, previous });
+        }
+        Ok(())
+    }
+
+    /// The value, as stored, in the first local tier holding it, along with when it expires.
+    async fn stored(&self, key: &str) -> Option<(T, Option<Expiry>)> {
+        for store in &self.stores {
+            let store = store.read().await;
+            if matches!(*store, Storage::Remote(_)) {
+                continue;
+            }
+            if let Ok(value) = store.read(key).await {
+                return Some((value, store.expiry(key).await.unwrap_or(None)));
+            }
+        }
+        None
+    }
+
+    /// Reverts the writes, most recent first, along with the quota they took.
+    async fn revert(&mut self, undo: Vec<Undo<T>>) {
+        for Undo { key, previous } in undo.into_iter().rev() {
+            let reverted = match previous {
+                Some((previous, expires)) => self.restore(&key, &previous, expires).await,
+                None => self.delete(&key).await,
+            };
+            if let Err(e) = reverted {
+                warn!("Failed to revert {key:?}: {e}");
+            }
+        }
+    }
+
+    /// Puts back the value written over, as it was stored, so beneath the seal, and until it
+    /// expired, accounting it in place of the value which replaced it.
+    async fn restore(&self, key: &str, previous: &T, expires: Option<Expiry>) -> Result<()> {
+        let written = self.peek_local(key).await;
+        self.place(key, previous, expires).await?;
+        if let Ok(written) = written {
+            self.release(&written);
+        }
+        self.quotas.record(
+            previous.scope().as_ref(),
+            1,
+            DataType::serialize(previous).len() as i64,
+        );
+        Ok(())
+    }
+}
+
+impl Models {
+    /// Commits the batch, writing its blocks before its entries, so that no entry is ever
+    /// visible before the blocks it refers to.
+    ///
+    /// If a write fails, those already made are reverted. If the process dies part way through,
+    /// the batch is rolled forward from the write-ahead log by `recover`. Changes are only
+    /// notified once the batch is committed, so none announce a write which is reverted, but for
+    /// evictions, which stand either way.
+    pub async fn commit(&mut self, batch: Batch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        // Sealed before they are logged, so the log never holds what the tiers wouldn't, and
+        // before they are checked, as a sealed block is addressed by its ciphertext. Those
+        // referred to by others in the batch must already be sealed, as `stage_path` does.
+        let batch = Batch {
+            blocks: batch
+                .blocks
+                .iter()
+                .map(|block| block.seal(self.keyring()))
+                .collect::<Result<_>>()?,
+            entries: batch.entries,
+        };
+        let denylist = self.denylist();
+        if batch
+            .blocks
+            .iter()
+            .any(|block| denylist.contains(&forbidden_as(&block.key())))
+            || batch
+                .entries
+                .iter()
+                .any(|entry| denylist.contains(&forbidden_as(&DataKey::key(entry))))
+        {
+            bail!(DataStoreError::AccessDenied);
+        }
+        if let Some(wal) = &self.wal {
+            wal.begin(&batch)?;
+        }
+
+        self.blocks.notifier.hold();
+        self.entries.notifier.hold();
+        let mut blocks = Vec::new();
+        let mut entries = Vec::new();
+        let mut applied = self.blocks.apply(&batch.blocks, &mut blocks).await;
+        if applied.is_ok() {
+            applied = self.entries.apply(&batch.entries, &mut entries).await;
+        }
+        if let Err(e) = applied {
+            warn!("Reverting a batch of {} values: {e}", batch.len());
+            self.entries.revert(entries).await;
+            self.blocks.revert(blocks).await;
+            let evicted = |change: &Change| matches!(change, Change::Evicted(_));
+            self.entries.notifier.release(evicted);
+            self.blocks.notifier.release(evicted);
+            if let Some(wal) = &self.wal {
+                wal.end()?;
+            }
+            return Err(e);
+        }
+
+        self.blocks.notifier.release(|_| true);
+        self.entries.notifier.release(|_| true);
+        if let Some(wal) = &self.wal {
+            wal.end()?;
+        }
+        self.put_outboards(&batch.blocks).await;
+        debug!(
+            "Committed {} blocks, and {} entries",
+            batch.blocks.len(),
+            batch.entries.len()
+        );
+        Ok(())
+    }
+
+    /// Rolls forward a batch left in the write-ahead log, returning whether there was one.
+    pub async fn recover(&mut self) -> Result<bool> {
+        let Some(wal) = &self.wal else {
+            return Ok(false);
+        };
+        let Some(batch) = wal.pending()? else {
+            return Ok(false);
+        };
+        info!("Recovering a batch of {} values", batch.len());
+        self.commit(batch).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::Hash;
+    use crate::storage::{Eviction, Keyring, Tier, TierConfig};
+    use std::time::Duration;
+
+    fn models(tiers: Vec<TierConfig>) -> Models {
+        Models::new(
+            Some(tiers),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .unwrap()
+    }
+
+    fn entry(name: &[u8], content: &[u8]) -> Entry {
+        Entry::new(Hash::new(name, None), &Block::padded(content))
+    }
+
+    #[tokio::test]
+    async fn failed_batch_is_rolled_back() {
+        // Refers to a sealed block, so takes more room than the entry it replaces.
+        let keyring = Keyring::default();
+        let sealed = Block::Composite {
+            timestamp: chrono::DateTime::UNIX_EPOCH,
+            confidence: 1,
+            scope: Some(keyring.insert("secret")),
+            data: Some(Box::new(Block::padded(b"sealed"))),
+            children: None,
+            chunking: None,
+        }
+        .seal(&keyring)
+        .unwrap();
+        let replacement = Entry::new(Hash::new(b"a", None), &sealed);
+        // Only the replacement fits, so the next write of the batch fails.
+        let mut models = models(vec![TierConfig {
+            max_size: Some(DataType::serialize(&replacement).len()),
+            eviction: Some(Eviction::Reject),
+            ..Tier::Process.into()
+        }]);
+
+        let previous = entry(b"a", b"previous");
+        let key = DataKey::key(&previous);
+        models.entries_mut().write(&key, &previous).await.unwrap();
+        let expires = Expiry::after(Duration::from_secs(60));
+        models.entries.stores[0]
+            .write()
+            .await
+            .expire(&key, Some(expires))
+            .await
+            .unwrap();
+        let usage = models.quotas().usage(None);
+
+        let mut batch = Batch::new();
+        batch
+            .put_entry(replacement)
+            .put_entry(entry(b"b", b"unwritten"));
+        assert!(models.commit(batch).await.is_err());
+
+        assert_eq!(models.entries().read(&key).await.unwrap(), previous);
+        let stored = models.entries.stores[0].read().await.expiry(&key).await;
+        assert_eq!(stored.unwrap(), Some(expires));
+        let unwritten = DataKey::key(&entry(b"b", b"unwritten"));
+        assert!(!models.entries().contains(&unwritten).await.unwrap());
+        assert_eq!(models.quotas().usage(None), usage);
+    }
+
+    #[tokio::test]
+    async fn logged_batch_is_replayed_after_a_crash() {
+        let dir = std::env::temp_dir().join(format!("gra-batch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let block = Block::padded(b"logged");
+        let entry = Entry::new(Hash::new(b"logged", None), &block);
+        let mut batch = Batch::new();
+        batch.put_block(block.clone()).put_entry(entry.clone());
+
+        {
+            let models = models(vec![Tier::Disk(dir.clone()).into()]);
+            // Logged, then the process dies before any of it is applied.
+            models.wal.as_ref().unwrap().begin(&batch).unwrap();
+        }
+
+        let mut models = models(vec![Tier::Disk(dir.clone()).into()]);
+        assert!(models.recover().await.unwrap());
+        assert_eq!(models.blocks().read(&block.key()).await.unwrap(), block);
+        let key = DataKey::key(&entry);
+        assert_eq!(models.entries().read(&key).await.unwrap(), entry);
+        assert!(!models.recover().await.unwrap());
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use hex;
 use std::{
     borrow::Borrow,
@@ -9,23 +9,38 @@ use std::{
 };
 
 use blake3::{Hasher, OUT_LEN};
+use bytes::Bytes;
 use chrono::Utc;
-use ciborium::{cbor, from_reader, into_writer};
+use ciborium::{cbor, from_reader, into_writer, Value};
 use ciborium_io::{Read, Write};
 use libp2p::{kad::Record, PeerId};
 use serde::de::IntoDeserializer;
 use zerocopy::AsBytes;
 
 use crate::{
-    hash::{CustomHash, Hash, HashOpts},
+    chunker::Chunker,
+    hash::{CustomHash, Hash, HashOpts, KeyVersion},
     reader::add_path,
-    storage::{DataKey, DataType},
+    storage::{DataKey, DataStoreError, DataType, Keyring},
 };
 
 type Confidence = usize;
 
 pub const BLOCK_SIZE: usize = 32;
 
+/// How long a `Block::Ref` is kept, locally, and as a record in the DHT.
+pub const REF_TTL: Duration = Duration::from_secs(3600);
+
+/// The CBOR head of a map of four fields, which a `Block::Sealed` is encoded as.
+const SEALED_HEAD: u8 = 0xa4;
+
+/// The byte the canonical encoding of each kind of block starts with, so that blocks of different
+/// kinds never share an address, e.g. a `Block::Ref` and the `Block::Bytes` of the hash it holds.
+const BYTES_TAG: u8 = 0x00;
+const REF_TAG: u8 = 0x01;
+const COMPOSITE_TAG: u8 = 0x02;
+const SEALED_TAG: u8 = 0x03;
+
 /// Terminology:
 /// - Block: A combination of words.
 /// - Chunk: A number of bytes, equal to BLOCK_SIZE.
@@ -46,6 +61,21 @@ pub enum Block {
         // data: Option<Vec<Box<Block>>>,
         data: Option<Box<Block>>,
         children: Option<Vec<Box<Block>>>,
+        /// How the content was split into the children, if by its content.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chunking: Option<Chunking>,
+    },
+    /// A scoped `Block::Composite`, encrypted with the key of its scope.
+    ///
+    /// Keeps the hash of the plaintext, which the ciphertext is bound to, and which the plaintext
+    /// is checked against once opened. The block itself is addressed by the hash of its
+    /// encoding, ciphertext included, so any node can check a sealed block it is sent, whether or
+    /// not it holds the scope.
+    Sealed {
+        scope: Hash,
+        hash: Hash,
+        nonce: Bytes,
+        ciphertext: Bytes,
     },
 }
 
@@ -58,6 +88,18 @@ impl Block {
         data: Vec<u8>,
         children: Option<Vec<Box<Block>>>,
     ) -> Self {
+        Block::Composite {
+            timestamp,
+            confidence,
+            scope,
+            data: Some(Box::new(Block::padded(&data))),
+            children, //children.into_iter().map(|block| Box::new(block)).collect(),
+            chunking: None,
+        }
+    }
+
+    /// The `Block::Bytes` holding the data, with its last chunk padded with zeros.
+    pub fn padded(data: &[u8]) -> Self {
         let mut chunks: Vec<[u8; BLOCK_SIZE]> = data
             .chunks_exact(BLOCK_SIZE)
             .map(|chunk| {
@@ -73,14 +115,7 @@ impl Block {
             partial_chunk[..chunk_len].copy_from_slice(&remaining[..chunk_len]);
             chunks.push(partial_chunk);
         };
-
-        Block::Composite {
-            timestamp,
-            confidence,
-            scope,
-            data: Some(Box::new(Block::Bytes(chunks))),
-            children, //children.into_iter().map(|block| Box::new(block)).collect(),
-        }
+        Block::Bytes(chunks)
     }
 
     // Serialize a block into CBOR
@@ -96,36 +131,169 @@ impl Block {
 
     // Deserialize a block from CBOR
     pub fn from_cbor(cbor_data: &[u8]) -> Result<Self> {
-        let block: Block = from_reader(cbor_data)?;
+        let block: Block = from_reader(cbor_data).map_err(|_| DataStoreError::Decode)?;
         Ok(block)
     }
-}
 
-impl Into<Hash> for Block {
-    fn into(self) -> Hash {
-        match &self {
-            Block::Ref(hash) => hash.clone(),
-            Block::Bytes(bytes) => {
-                let flattened: Vec<u8> = bytes.iter().flat_map(|hash| hash.to_owned()).collect();
-                Hash::new(flattened.as_slice(), None)
+    /// The `Block::Bytes` of its canonical encoding, failing with `Decode` if that isn't the tag of
+    /// a `Block::Bytes`, followed by a whole number of chunks.
+    pub fn from_chunks(encoded: &[u8]) -> Result<Self> {
+        let content = match encoded.split_first() {
+            Some((&BYTES_TAG, content)) if content.len() % BLOCK_SIZE == 0 => content,
+            _ => bail!(DataStoreError::Decode),
+        };
+        Ok(Block::Bytes(
+            content
+                .chunks_exact(BLOCK_SIZE)
+                .map(|chunk| {
+                    <[u8; BLOCK_SIZE]>::try_from(chunk)
+                        .expect(&format!("Chunk length is guaranteed to be {BLOCK_SIZE}"))
+                })
+                .collect(),
+        ))
+    }
+
+    /// Returns the hashes of the blocks referenced, through `Block::Ref`, by this block, or any
+    /// block nested within it.
+    pub fn references(&self) -> Vec<Hash> {
+        match self {
+            Block::Ref(hash) => vec![hash.to_owned()],
+            // The references of a sealed block are hidden, until it is opened.
+            Block::Bytes(_) | Block::Sealed { .. } => Vec::new(),
+            Block::Composite { data, children, .. } => data
+                .iter()
+                .chain(children.iter().flatten())
+                .flat_map(|block| block.references())
+                .collect(),
+        }
+    }
+
+    /// The canonical encoding of the block's content, which is all its hash covers, led by the tag
+    /// of its kind.
+    ///
+    /// Raw bytes, and references, are encoded as they are. A composite is encoded as deterministic
+    /// CBOR, i.e. definite lengths, and the shortest heads, of the fixed sequence
+    /// `[data hash | null, [child hash, ...] | null]`, followed by `[min, avg, max, [len, ...]]`
+    /// if it was split by content, leaving out its metadata, so that the same content has the same
+    /// address, whenever, and by whoever, it is added. A sealed block is encoded as the hash of its
+    /// plaintext, the length of its nonce, as eight little-endian bytes, its nonce, then its
+    /// ciphertext.
+    pub fn canonical(&self) -> Vec<u8> {
+        let (tag, content) = match self {
+            Block::Bytes(bytes) => (BYTES_TAG, bytes.concat()),
+            Block::Ref(hash) => (REF_TAG, hash.as_bytes().to_vec()),
+            Block::Composite {
+                data,
+                children,
+                chunking,
+                ..
+            } => {
+                let hash = |block: &Box<Block>| Value::Bytes(block.hash().to_vec());
+                let mut content = vec![
+                    data.as_ref().map_or(Value::Null, hash),
+                    children.as_ref().map_or(Value::Null, |children| {
+                        Value::Array(children.iter().map(hash).collect())
+                    }),
+                ];
+                if let Some(Chunking { chunker, lens }) = chunking {
+                    let size = |size: u32| Value::Integer(size.into());
+                    content.push(Value::Array(vec![
+                        size(chunker.min()),
+                        size(chunker.avg()),
+                        size(chunker.max()),
+                        Value::Array(lens.iter().copied().map(size).collect()),
+                    ]));
+                }
+                let content = Value::Array(content);
+                let mut encoded = Vec::new();
+                into_writer(&content, &mut encoded).expect("CBOR values to serialize");
+                (COMPOSITE_TAG, encoded)
             }
+            Block::Sealed {
+                hash,
+                nonce,
+                ciphertext,
+                ..
+            } => (
+                SEALED_TAG,
+                [
+                    hash.as_bytes().as_slice(),
+                    &(nonce.len() as u64).to_le_bytes(),
+                    nonce,
+                    ciphertext,
+                ]
+                .concat(),
+            ),
+        };
+        let mut encoded = Vec::with_capacity(1 + content.len());
+        encoded.push(tag);
+        encoded.extend(content);
+        encoded
+    }
+
+    /// When, and how confidently, a composite was made, which is carried beside its content, and
+    /// isn't part of its address.
+    pub fn metadata(&self) -> Option<Metadata> {
+        match self {
             Block::Composite {
                 timestamp,
                 confidence,
-                scope,
-                data,
-                children,
-            } => Hash::new(
-                self.borrow()
-                    .to_cbor()
-                    .expect("Failed to serialize to CBOR")
-                    .as_bytes(),
-                Some(HashOpts { key: scope.clone() }),
+                ..
+            } => Some(Metadata {
+                timestamp: *timestamp,
+                confidence: *confidence,
+            }),
+            _ => None,
+        }
+    }
+
+    /// How a composite was split, by content, into its children, if it was.
+    pub fn chunking(&self) -> Option<&Chunking> {
+        match self {
+            Block::Composite { chunking, .. } => chunking.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The hash the block is addressed by, scoped as the block is, or the one it refers to.
+    pub fn address(&self) -> Hash {
+        match self {
+            Block::Ref(hash) => hash.clone(),
+            Block::Bytes(_) => Hash::from_bytes(&self.hash(), None),
+            Block::Composite { scope, .. } => {
+                Hash::from_bytes(&self.hash(), Some(HashOpts { key: scope.clone() }))
+            }
+            Block::Sealed { scope, .. } => Hash::from_bytes(
+                &self.hash(),
+                Some(HashOpts {
+                    key: Some(scope.clone()),
+                }),
             ),
         }
     }
 }
 
+/// How a file was split, by its content, into the leaf blocks a `Block::Composite` refers to.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq, Hash, PartialOrd)]
+pub struct Chunking {
+    pub chunker: Chunker,
+    /// The length of each leaf, in order, before its last chunk was padded.
+    pub lens: Vec<u32>,
+}
+
+/// The metadata of a `Block::Composite`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub confidence: Confidence,
+}
+
+impl Into<Hash> for Block {
+    fn into(self) -> Hash {
+        self.address()
+    }
+}
+
 impl Into<Vec<u8>> for Block {
     fn into(self) -> Vec<u8> {
         let mut encoded = Vec::new();
@@ -136,77 +304,112 @@ impl Into<Vec<u8>> for Block {
 
 impl DataKey for Block {
     fn key(&self) -> String {
-        String::from_utf8_lossy(&self.hash()).to_string()
+        hex::encode(self.hash())
     }
 }
 
 impl CustomHash for Block {
+    /// Hashes the canonical encoding, keyed by the scope, if any.
     fn hash(&self) -> [u8; OUT_LEN] {
-        let mut hasher = match self {
-            Block::Composite { scope, .. } => match scope {
-                Some(scope) => blake3::Hasher::new_keyed(scope.as_bytes()),
-                None => blake3::Hasher::new(),
-            },
-            _ => blake3::Hasher::new(),
-        };
-
         match self {
-            Block::Ref(hash) => {
-                hasher.update(hash.as_bytes());
-            }
-            Block::Bytes(bytes) => bytes.iter().for_each(|hash| {
-                hasher.update(hash.as_bytes());
-            }),
             Block::Composite {
-                timestamp,
-                confidence,
-                scope,
-                data,
-                children,
-            } => {
-                hasher.update(timestamp.to_utc().to_string().as_bytes());
-                hasher.update(confidence.as_bytes());
-                if let Some(data) = data {
-                    hasher.update(
-                        &data
-                            .to_cbor()
-                            .expect("Failed to serialize Block")
-                            .as_bytes(),
-                    );
-                } else {
-                    hasher.update(&[]);
-                };
-
-                children.iter().for_each(|element| {
-                    hasher.update(
-                        element
-                            .iter()
-                            .flat_map(|el| {
-                                el.to_owned().to_cbor().expect("Failed to serialize Block")
-                            })
-                            .collect::<Vec<u8>>()
-                            .as_slice(),
-                    );
-                });
+                scope: Some(scope), ..
             }
+            | Block::Sealed { scope, .. } => {
+                *blake3::keyed_hash(scope.as_bytes(), &self.canonical()).as_bytes()
+            }
+            _ => *blake3::hash(&self.canonical()).as_bytes(),
         }
-
-        *hasher.finalize().as_bytes()
     }
 }
 
 impl DataType for Block {
+    const NAMESPACE: &'static str = "blocks";
+
     fn serialize(&self) -> Vec<u8> {
         let mut encoded = Vec::new();
         into_writer(self, &mut encoded).expect("Failed to serialize Block");
         encoded
     }
 
-    fn deserialize(data: &[u8]) -> Self
+    fn deserialize(data: &[u8]) -> Result<Self>
     where
         Self: Sized,
     {
-        from_reader(data).expect("Failed to decode Block")
+        Block::from_cbor(data)
+    }
+
+    fn ttl(&self) -> Option<Duration> {
+        match self {
+            Block::Ref(_) => Some(REF_TTL),
+            _ => None,
+        }
+    }
+
+    fn scope(&self) -> Option<Hash> {
+        match self {
+            Block::Composite { scope, .. } => scope.to_owned(),
+            Block::Sealed { scope, .. } => Some(scope.to_owned()),
+            _ => None,
+        }
+    }
+
+    /// Seals a `Block::Composite` within a scope, binding the ciphertext to the plaintext hash.
+    ///
+    /// Sealing is deterministic, so the sealed block, and its address, are the same whenever the
+    /// same composite, metadata included, is sealed.
+    fn seal(&self, keyring: &Keyring) -> Result<Self> {
+        let Block::Composite {
+            scope: Some(scope), ..
+        } = self
+        else {
+            return Ok(self.clone());
+        };
+        let hash = Hash::from_bytes(
+            &self.hash(),
+            Some(HashOpts {
+                key: Some(scope.clone()),
+            }),
+        );
+        let (nonce, ciphertext) = keyring.seal(scope, hash.as_bytes(), &self.serialize())?;
+        Ok(Block::Sealed {
+            scope: scope.clone(),
+            hash,
+            nonce: nonce.to_vec().into(),
+            ciphertext: ciphertext.into(),
+        })
+    }
+
+    fn open(&self, keyring: &Keyring) -> Result<Self> {
+        let Block::Sealed {
+            scope,
+            hash,
+            nonce,
+            ciphertext,
+        } = self
+        else {
+            return Ok(self.clone());
+        };
+        if !keyring.contains(scope) {
+            return Ok(self.clone());
+        }
+        let data = keyring.open(scope, hash.as_bytes(), nonce, ciphertext)?;
+        let block = Block::from_cbor(&data)?;
+        if block.hash() != *hash.as_bytes() {
+            bail!(DataStoreError::Corrupt);
+        }
+        Ok(block)
+    }
+
+    /// Every block is hashed again from its content, and sealed blocks in a scope which is held
+    /// are also opened, which checks the hash of what they hold.
+    fn is_intact(&self, key: &str, keyring: &Keyring) -> bool {
+        self.key() == key && self.open(keyring).is_ok()
+    }
+
+    /// Untagged variants carry no tag, so a sealed block is told apart by the head of its map.
+    fn is_sealed(data: &[u8]) -> bool {
+        data.first() == Some(&SEALED_HEAD)
     }
 }
 
@@ -298,12 +501,16 @@ impl DataType for Block {
 
 impl Into<Record> for Block {
     fn into(self) -> Record {
+        // Records live for as long as the block would, when stored locally. A record has one
+        // key, so it goes under the latest version only; older nodes find the block through
+        // its provider records, which are published under every version.
+        let expires = self.ttl().map(|ttl| Instant::now() + ttl);
         match self {
             Block::Ref(hash) => Record {
-                key: hash.into(),
+                key: hash.record_key(KeyVersion::LATEST),
                 value: Vec::new(),
                 publisher: None,
-                expires: Some(Instant::now() + Duration::from_secs(3600)),
+                expires,
             },
             Block::Bytes(bytes) => Record {
                 key: bytes
@@ -313,7 +520,7 @@ impl Into<Record> for Block {
                     .into(),
                 value: Vec::new(),
                 publisher: None,
-                expires: None,
+                expires,
             },
             Block::Composite {
                 timestamp,
@@ -321,15 +528,22 @@ impl Into<Record> for Block {
                 ref scope,
                 ref data,
                 ref children,
+                ..
             } => {
                 let hash: Hash = self.clone().into();
                 Record {
-                    key: hash.into(),
+                    key: hash.record_key(KeyVersion::LATEST),
                     value: self.to_cbor().expect("Failed to serialize Block"),
                     publisher: Some(PeerId::random()),
-                    expires: None,
+                    expires,
                 }
             }
+            Block::Sealed { .. } => Record {
+                key: self.address().record_key(KeyVersion::LATEST),
+                value: self.to_cbor().expect("Failed to serialize Block"),
+                publisher: None,
+                expires,
+            },
         }
         // let key = match self.3 {
         //     Some(k) => {
@@ -348,9 +562,11 @@ impl Into<Record> for Block {
     }
 }
 
-impl From<&[u8]> for Block {
-    fn from(value: &[u8]) -> Self {
-        from_reader(value).expect("Failed to decode Block")
+impl TryFrom<&[u8]> for Block {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Block::from_cbor(value)
     }
 }
 
@@ -402,3 +618,60 @@ impl From<&[u8]> for Block {
 //         Ok(block)
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed(keyring: &Keyring) -> Block {
+        let scope = keyring.insert("secret");
+        Block::Composite {
+            timestamp: chrono::DateTime::UNIX_EPOCH,
+            confidence: 1,
+            scope: Some(scope),
+            data: Some(Box::new(Block::padded(b"sealed content"))),
+            children: None,
+            chunking: None,
+        }
+        .seal(keyring)
+        .unwrap()
+    }
+
+    #[test]
+    fn sealing_is_deterministic() {
+        let keyring = Keyring::default();
+        let block = sealed(&keyring);
+        assert!(matches!(block, Block::Sealed { .. }));
+        assert_eq!(block.address(), sealed(&keyring).address());
+        assert!(block.is_intact(&block.key(), &keyring));
+    }
+
+    #[test]
+    fn tampered_sealed_block_is_rejected() {
+        let keyring = Keyring::default();
+        let block = sealed(&keyring);
+        let Block::Sealed {
+            scope,
+            hash,
+            nonce,
+            ciphertext,
+        } = block.clone()
+        else {
+            unreachable!();
+        };
+        let mut flipped = ciphertext.to_vec();
+        flipped[0] ^= 1;
+        let tampered = Block::Sealed {
+            scope,
+            hash,
+            nonce,
+            ciphertext: flipped.into(),
+        };
+
+        // Caught by its address, even by a node which doesn't hold the scope.
+        assert_ne!(tampered.hash(), block.hash());
+        assert!(block.is_intact(&block.key(), &Keyring::default()));
+        assert!(!tampered.is_intact(&block.key(), &Keyring::default()));
+        assert!(tampered.open(&keyring).is_err());
+    }
+}
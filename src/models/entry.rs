@@ -1,4 +1,5 @@
-use ciborium::cbor;
+use anyhow::{bail, Result};
+use ciborium::{cbor, from_reader, into_writer};
 use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -9,7 +10,7 @@ use std::{
 use crate::{
     hash::{CustomHash, Hash, HashOpts},
     models::Block,
-    storage::{DataKey, DataType},
+    storage::{DataKey, DataStoreError, DataType},
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
@@ -17,19 +18,7 @@ pub struct Entry(Hash, Hash);
 
 impl Entry {
     pub fn new(hash: Hash, block: &Block) -> Self {
-        let block_ref = match block {
-            Block::Ref(hash) => hash.to_owned(),
-            Block::Bytes(bytes) => Hash::from_bytes(&block.hash(), None),
-            Block::Composite {
-                timestamp,
-                confidence,
-                scope,
-                data,
-                children,
-            } => Hash::from_bytes(&block.hash(), Some(HashOpts { key: scope.clone() })),
-        };
-
-        Self(hash, block_ref)
+        Self(hash, block.address())
     }
 
     pub fn key(&self) -> &Hash {
@@ -39,23 +28,65 @@ impl Entry {
     pub fn value(&self) -> &Hash {
         &self.1
     }
+
+    /// The digest stored alongside the value, keyed by the hash of the entry, so a value which
+    /// changes at rest no longer matches it.
+    fn digest(&self) -> Hash {
+        Hash::from_bytes(
+            blake3::keyed_hash(self.0.as_bytes(), self.1.as_bytes()).as_bytes(),
+            None,
+        )
+    }
+}
+
+/// An entry as stored, with the digest of its value, or without, as written by older nodes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Digested(Hash, Hash, Hash),
+    Bare(Hash, Hash),
 }
 
 impl DataKey for Entry {
+    /// Formatted as `scope|hash` when scoped, so entries can be listed by scope.
     fn key(&self) -> String {
-        self.0.to_hex()
+        self.0.to_string()
     }
 }
 
 impl DataType for Entry {
+    const NAMESPACE: &'static str = "entries";
+
     fn serialize(&self) -> Vec<u8> {
-        let encoded = cbor!(self).expect("Failed to serialize Entry");
+        let mut encoded = Vec::new();
+        into_writer(&(&self.0, &self.1, self.digest()), &mut encoded)
+            .expect("Entry to serialize, as every hash does");
         encoded
-            .into_bytes()
-            .expect("Failed to convert Entry to bytes")
     }
 
-    fn deserialize(bytes: &[u8]) -> Self {
-        ciborium::de::from_reader(bytes).expect("Failed to deserialize Entry")
+    fn deserialize(bytes: &[u8]) -> Result<Self> {
+        Entry::try_from(bytes)
+    }
+
+    fn scope(&self) -> Option<Hash> {
+        self.0.key().cloned()
+    }
+}
+
+impl TryFrom<&[u8]> for Entry {
+    type Error = anyhow::Error;
+
+    /// Fails with `Corrupt` if the value no longer matches its digest.
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        match from_reader(bytes).map_err(|_| DataStoreError::Decode)? {
+            Stored::Digested(key, value, digest) => {
+                let entry = Entry(key, value);
+                if entry.digest() != digest {
+                    bail!(DataStoreError::Corrupt);
+                }
+                Ok(entry)
+            }
+            Stored::Bare(key, value) => Ok(Entry(key, value)),
+        }
     }
 }
@@ -0,0 +1,240 @@
+use anyhow::Result;
+use hashbrown::HashSet;
+use tracing::{debug, info, warn};
+
+use super::{Block, Entry, Models};
+use crate::hash::Hash;
+use crate::storage::{DataKey, DataStore, DataStoreError, DataType, ListOptions};
+
+/// The outcome of a garbage collection pass.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcReport {
+    /// Blocks reachable from a pin, and so kept.
+    pub reachable: usize,
+    /// Blocks unreachable from every pin, and deleted unless this was a dry run.
+    pub swept: Vec<String>,
+    /// Bytes held by the swept blocks, summed over every local tier holding them.
+    pub reclaimable: u64,
+    /// Blocks kept, though unreachable as far as was seen, as they are sealed in a scope which
+    /// isn't held, or in which a reachable block couldn't be opened, so may be reached through it.
+    pub unopened: usize,
+    /// Reachable blocks which are held, but couldn't be read, or opened, to be followed. Should
+    /// one not be sealed, nothing is swept, as any block may be reached through it.
+    pub unfollowed: Vec<String>,
+    pub dry_run: bool,
+}
+
+impl Models {
+    /// Pins the entry, keeping it and every block it reaches through garbage collection.
+    pub async fn pin(&self, key: &str) -> Result<()> {
+        self.entries.read(key).await?;
+        self.pins.insert(key)?;
+        Ok(())
+    }
+
+    pub async fn unpin(&self, key: &str) -> Result<()> {
+        self.pins.remove(key)?;
+        Ok(())
+    }
+
+    pub fn pins(&self) -> Vec<String> {
+        self.pins.keys()
+    }
+
+    /// Marks every block reachable from a pinned entry, and sweeps the rest.
+    ///
+    /// Traversal follows the block referenced by each pinned entry, then every `Block::Ref`
+    /// nested within the blocks it reaches, including those inside `Block::Composite` children.
+    /// Only local tiers are looked in, since a block must be held to be followed. Sealed blocks
+    /// are opened to be traversed. One which can't be, as its scope isn't held, or it fails to
+    /// open, keeps every block sealed in that scope, as any of them may be reached through it. A
+    /// reachable block which can't be read at all keeps every block.
+    pub async fn gc(&mut self, dry_run: bool) -> Result<GcReport> {
+        let marked = self.mark().await?;
+        let mut report = GcReport {
+            reachable: marked.reachable.len(),
+            unfollowed: marked.unfollowed,
+            dry_run,
+            ..Default::default()
+        };
+        if marked.blind {
+            warn!("Sweeping nothing, as a reachable block couldn't be followed");
+            return Ok(report);
+        }
+
+        for listed in self.blocks.locate(&ListOptions::default()).await? {
+            if marked.reachable.contains(&listed.key) {
+                continue;
+            }
+            let block = self.blocks.peek_local(&listed.key).await;
+            if let Ok(Block::Sealed { scope, .. }) = &block {
+                if marked.unopened.contains(scope) {
+                    report.unopened += 1;
+                    continue;
+                }
+            }
+            if let Ok(block) = block {
+                report.reclaimable += (block.serialize().len() * listed.tiers.len()) as u64;
+            }
+            if !dry_run {
+                self.blocks.delete(&listed.key).await?;
+                // Its outboard, if it had one, goes with it.
+                let _ = self.outboards.delete(&listed.key).await;
+            }
+            report.swept.push(listed.key);
+        }
+
+        info!(
+            "Garbage collection swept {} blocks, {} bytes, keeping {}, and {} unopened{}",
+            report.swept.len(),
+            report.reclaimable,
+            report.reachable,
+            report.unopened,
+            if dry_run { " (dry run)" } else { "" }
+        );
+        Ok(report)
+    }
+
+    /// Marks every block reachable from a pinned entry, noting the scopes of those which couldn't
+    /// be opened to be followed, and whether any which couldn't even be read were met.
+    async fn mark(&self) -> Result<Marked> {
+        let mut marked = Marked::default();
+        let mut pending: Vec<String> = Vec::new();
+
+        for pin in self.pins.keys() {
+            match self.entries.peek_local(&pin).await {
+                Ok(entry) => pending.push(entry.value().to_hex()),
+                Err(e) => warn!("Pinned entry {pin:?} is missing: {e}"),
+            }
+        }
+
+        while let Some(key) = pending.pop() {
+            if !marked.reachable.insert(key.to_owned()) {
+                continue;
+            }
+            let block = match self.blocks.peek_local(&key).await {
+                Ok(block) => block,
+                Err(e) if DataStoreError::of(&e) == Some(DataStoreError::NotFound) => {
+                    debug!("Reachable block {key:?} is not held locally");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Reachable block {key:?} can't be read: {e}");
+                    marked.unfollowed.push(key);
+                    marked.blind = true;
+                    continue;
+                }
+            };
+            if let Block::Sealed { scope, .. } = &block {
+                if !self.keyring().contains(scope) {
+                    warn!("Reachable block {key:?} is sealed, in a scope which isn't held");
+                    marked.unopened.insert(scope.to_owned());
+                    continue;
+                }
+            }
+            match block.open(self.keyring()) {
+                Ok(block) => pending.extend(
+                    block
+                        .references()
+                        .iter()
+                        .map(|hash| hash.to_hex())
+                        .filter(|key| !marked.reachable.contains(key)),
+                ),
+                Err(e) => {
+                    warn!("Reachable block {key:?} can't be opened: {e}");
+                    match block {
+                        Block::Sealed { scope, .. } => {
+                            marked.unopened.insert(scope);
+                        }
+                        _ => marked.blind = true,
+                    }
+                    marked.unfollowed.push(key);
+                }
+            }
+        }
+
+        Ok(marked)
+    }
+}
+
+/// The blocks a garbage collection found reachable, and what it couldn't see past.
+#[derive(Debug, Default)]
+struct Marked {
+    reachable: HashSet<String>,
+    /// Scopes of reachable blocks which couldn't be opened, so every block sealed in them is kept.
+    unopened: HashSet<Hash>,
+    unfollowed: Vec<String>,
+    /// Whether a reachable block couldn't be followed, and isn't sealed, so may reach any block.
+    blind: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Keyring;
+    use std::sync::Arc;
+
+    fn sealed(keyring: &Keyring, scope: &Hash, content: &[u8]) -> Block {
+        Block::Composite {
+            timestamp: chrono::DateTime::UNIX_EPOCH,
+            confidence: 1,
+            scope: Some(scope.to_owned()),
+            data: Some(Box::new(Block::padded(content))),
+            children: None,
+            chunking: None,
+        }
+        .seal(keyring)
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn block_which_fails_to_open_is_reported_and_keeps_its_scope() {
+        let keyring = Arc::new(Keyring::default());
+        let scope = keyring.insert("secret");
+        let mut models = Models::new(
+            None,
+            Default::default(),
+            Default::default(),
+            keyring.clone(),
+            Default::default(),
+        )
+        .unwrap();
+
+        let Block::Sealed {
+            scope: sealed_in,
+            hash,
+            nonce,
+            ciphertext,
+        } = sealed(&keyring, &scope, b"pinned")
+        else {
+            unreachable!();
+        };
+        let mut flipped = ciphertext.to_vec();
+        flipped[0] ^= 1;
+        let tampered = Block::Sealed {
+            scope: sealed_in,
+            hash,
+            nonce,
+            ciphertext: flipped.into(),
+        };
+        let sibling = sealed(&keyring, &scope, b"sibling");
+        let garbage = Block::padded(b"garbage");
+        for block in [&tampered, &sibling, &garbage] {
+            models
+                .blocks_mut()
+                .write(&block.key(), block)
+                .await
+                .unwrap();
+        }
+        let entry = Entry::new(Hash::new(b"pinned", None), &tampered);
+        let key = DataKey::key(&entry);
+        models.entries_mut().write(&key, &entry).await.unwrap();
+        models.pin(&key).await.unwrap();
+
+        let report = models.gc(false).await.unwrap();
+        assert_eq!(report.unfollowed, [tampered.key()]);
+        assert_eq!(report.swept, [garbage.key()]);
+        assert_eq!(report.unopened, 1);
+        assert!(models.blocks().contains(&sibling.key()).await.unwrap());
+    }
+}
@@ -1,13 +1,21 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use anyhow::{bail, Result};
 use async_std::io;
+use async_std::sync::RwLock;
 use async_trait::async_trait;
+use bytes::Bytes;
+use tracing::{debug, warn};
 
-use crate::storage::{DataStore, DataStoreError, DataType, MemoryStorage, Storage, Tier};
+use crate::hash::Hash;
+use crate::storage::{
+    Change, Changes, DataStore, DataStoreError, DataType, Denylist, Expiry, Filter, Keyring,
+    ListOptions, Listed, Notifier, Pins, ProcessStorage, Quotas, Storage, Tier, TierConfig, Usage,
+};
 
 mod block;
-pub use block::{Block, BLOCK_SIZE};
+pub use block::{Block, Chunking, Metadata, BLOCK_SIZE};
 
 mod entry;
 pub use entry::Entry;
@@ -15,46 +23,376 @@ pub use entry::Entry;
 mod peer;
 pub use peer::Peer;
 
+mod batch;
+pub use batch::{Batch, Wal};
+
+mod gc;
+pub use gc::GcReport;
+
+mod scrub;
+pub use scrub::ScrubReport;
+
+mod outboard;
+pub use outboard::{Outboard, Slice, Verifier};
+
+mod sweep;
+
 pub type Confidence = u64;
 
 pub struct Models {
     blocks: Model<Block>,
     entries: Model<Entry>,
+    outboards: Model<Outboard>,
+    pins: Arc<Pins>,
+    wal: Option<Wal>,
     // TODO: Add Peers, with fingerprint as key. Enables closest search
     // peers: Model<Fingerprint, Peer>,
 }
 
 impl Models {
-    pub fn new(storage_tiers: Option<Vec<Tier>>) -> Result<Self> {
-        let tiers = storage_tiers.unwrap_or(vec![Tier::Memory]);
+    /// Opens the tiers, in order, defaulting to a single in-process tier.
+    pub fn new(
+        storage_tiers: Option<Vec<TierConfig>>,
+        denylist: Arc<Denylist>,
+        pins: Arc<Pins>,
+        keyring: Arc<Keyring>,
+        quotas: Arc<Quotas>,
+    ) -> Result<Self> {
+        let tiers = storage_tiers.unwrap_or(vec![Tier::Process.into()]);
+        // Peers only serve blocks, so entries, and outboards, are kept to the local tiers.
+        let local_tiers: Vec<TierConfig> = tiers
+            .iter()
+            .filter(|config| !matches!(config.tier, Tier::Remote(_)))
+            .cloned()
+            .collect();
+        // Batches only need logging if they can outlive the process.
+        let wal = tiers.iter().find_map(|config| match &config.tier {
+            Tier::Disk(root) | Tier::Pack(root) => Some(Wal::new(root.join("wal"))),
+            _ => None,
+        });
         Ok(Self {
-            blocks: Model::<Block>::new(&tiers)?,
-            entries: Model::<Entry>::new(&tiers)?,
+            blocks: Model::<Block>::new(&tiers, denylist.clone(), keyring.clone(), quotas.clone())?,
+            entries: Model::<Entry>::new(
+                &local_tiers,
+                denylist.clone(),
+                keyring.clone(),
+                quotas.clone(),
+            )?,
+            outboards: Model::<Outboard>::new(&local_tiers, denylist, keyring, quotas)?,
+            pins,
+            wal,
         })
     }
+
+    pub fn denylist(&self) -> &Denylist {
+        &self.blocks.denylist
+    }
+
+    pub fn keyring(&self) -> &Keyring {
+        &self.blocks.keyring
+    }
+
+    /// The usage of every scope, by both blocks, and entries, against their quotas.
+    pub fn quotas(&self) -> &Quotas {
+        &self.blocks.quotas
+    }
+
+    /// Deletes every value the denylist forbids, e.g. once a list is imported, returning how many.
+    pub async fn purge(&self) -> Result<usize> {
+        Ok(self.blocks.purge().await?
+            + self.entries.purge().await?
+            + self.outboards.purge().await?)
+    }
+
+    /// Counts every value held locally against its scope, e.g. after opening persistent tiers.
+    pub async fn recount(&self) -> Result<()> {
+        self.quotas().reset();
+        self.blocks.recount().await?;
+        self.entries.recount().await?;
+        self.outboards.recount().await
+    }
+
+    pub fn blocks(&self) -> &Model<Block> {
+        &self.blocks
+    }
+
+    pub fn blocks_mut(&mut self) -> &mut Model<Block> {
+        &mut self.blocks
+    }
+
+    pub fn entries(&self) -> &Model<Entry> {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> &mut Model<Entry> {
+        &mut self.entries
+    }
 }
 
+/// A tiered store, ordered from the fastest tier to the slowest.
+///
+/// Writes land in the first tier, values evicted from a tier are demoted to the one below it,
+/// and reads which hit a lower tier promote the value back to the first.
+///
+/// Values are sealed with the keyring before they reach any tier, and opened as they are read.
+/// Each value is accounted to its scope once, however many tiers hold it, and writes which would
+/// exceed its quota are refused.
 #[derive(Debug)]
 pub struct Model<T: DataType> {
-    stores: Vec<Storage<T>>,
+    names: Vec<String>,
+    stores: Vec<RwLock<Storage<T>>>,
+    notifier: Notifier,
+    denylist: Arc<Denylist>,
+    keyring: Arc<Keyring>,
+    quotas: Arc<Quotas>,
+}
+
+/// The form a key is forbidden in, the hex of its hash, without any scope, so a hash forbids both
+/// its block, and the entries of every scope under it.
+fn forbidden_as(key: &str) -> String {
+    key.parse::<Hash>()
+        .map_or_else(|_| key.to_owned(), |hash| hash.to_hex())
 }
 
 impl<T: DataType> Model<T> {
-    fn new(tiers: &Vec<Tier>) -> Result<Self> {
+    fn new(
+        tiers: &Vec<TierConfig>,
+        denylist: Arc<Denylist>,
+        keyring: Arc<Keyring>,
+        quotas: Arc<Quotas>,
+    ) -> Result<Self> {
         if tiers.is_empty() {
             // TODO: Check POSIX, and return appropriate error
             bail!(DataStoreError::Invalid);
         }
         Ok(Self {
-            stores: tiers.iter().cloned().map(|tier| tier.into()).collect(),
+            names: tiers.iter().map(|config| config.name.to_owned()).collect(),
+            stores: tiers
+                .iter()
+                .cloned()
+                .map(|config| Storage::try_from(config).map(RwLock::new))
+                .collect::<Result<_>>()?,
+            notifier: Notifier::default(),
+            denylist,
+            keyring,
+            quotas,
         })
     }
 }
 
+impl<T: DataType> Model<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Stores the value in the first tier that accepts it, cascading evictions down the tiers.
+    ///
+    /// Evicted values keep their expiry as they are demoted, so moving between tiers never
+    /// extends their lifetime. The network keeps no bytes, so it is never a target, and values
+    /// evicted from the last local tier are withdrawn from it rather than announced. Fails with
+    /// `NoSpace` unless some tier still holds the value once every eviction has settled.
+    async fn place(&self, key: &str, value: &T, expires: Option<Expiry>) -> Result<()> {
+        let mut pending = vec![(key.to_owned(), value.to_owned(), expires)];
+
+        for (tier, store) in self.stores.iter().enumerate() {
+            if pending.is_empty() {
+                break;
+            }
+            // Tiers are inclusive, so a demoted value may already be held below. That is asked
+            // before taking the write lock, so the tier isn't held while looking.
+            let held = {
+                let store = store.read().await;
+                if matches!(*store, Storage::Remote(_)) {
+                    continue;
+                }
+                let mut held = Vec::new();
+                for (pending_key, _, _) in &pending {
+                    if pending_key != key && store.contains(pending_key).await.unwrap_or(false) {
+                        held.push(pending_key.to_owned());
+                    }
+                }
+                held
+            };
+            let mut store = store.write().await;
+            let mut overflow = Vec::new();
+            for (pending_key, pending_value, pending_expires) in pending {
+                if held.contains(&pending_key) {
+                    continue;
+                }
+                match store
+                    .insert(&pending_key, &pending_value, pending_expires)
+                    .await
+                {
+                    Ok(evicted) => overflow.extend(evicted),
+                    Err(e) => {
+                        debug!("Tier {tier} rejected {pending_key:?}: {e}");
+                        overflow.push((pending_key, pending_value, pending_expires));
+                    }
+                }
+            }
+            pending = overflow;
+        }
+
+        // Whether every tier rejected the value, or it was placed, then evicted in turn from
+        // every tier below.
+        let lost = pending.iter().any(|(pending_key, _, _)| pending_key == key);
+        for (dropped, value, _) in pending
+            .into_iter()
+            .filter(|(pending_key, _, _)| pending_key != key)
+        {
+            // Tiers are inclusive, so one above may still hold it, e.g. as it was promoted. It is
+            // only gone, and its quota released, once no local tier does.
+            if self.peek_local(&dropped).await.is_ok() {
+                debug!("Evicted {dropped:?} from the last local tier, though it is held above");
+                continue;
+            }
+            warn!("Evicted {dropped:?} from the last local tier");
+            for store in &self.stores {
+                let mut store = store.write().await;
+                if matches!(*store, Storage::Remote(_)) {
+                    if let Err(e) = store.delete(&dropped).await {
+                        debug!("Failed to withdraw {dropped:?}: {e}");
+                    }
+                }
+            }
+            self.release(&value);
+            self.notifier.notify(Change::Evicted(dropped));
+        }
+
+        if lost {
+            bail!(DataStoreError::NoSpace);
+        }
+        Ok(())
+    }
+
+    /// Writes the value, as `write` does, though only sharing the model, since every tier is
+    /// locked as it is reached, e.g. so it can be written to while scrubbed, or swept.
+    pub async fn put(&self, key: &str, data: &T) -> Result<()> {
+        if self.denylist.contains(&forbidden_as(key)) {
+            bail!(DataStoreError::AccessDenied);
+        }
+        let data = data.seal(&self.keyring)?;
+        let scope = data.scope();
+        let len = DataType::serialize(&data).len() as i64;
+        let (objects, bytes) = match self.peek_local(key).await {
+            Ok(previous) => (0, len - DataType::serialize(&previous).len() as i64),
+            Err(_) => (1, len),
+        };
+        self.quotas.reserve(scope.as_ref(), objects, bytes)?;
+        if let Err(e) = self.place(key, &data, data.ttl().map(Expiry::after)).await {
+            self.quotas.record(scope.as_ref(), -objects, -bytes);
+            return Err(e);
+        }
+        self.notifier.notify(Change::Written(key.to_owned()));
+        Ok(())
+    }
+
+    /// Reads the value, as stored, from the first local tier holding it, so without reaching out
+    /// to the network.
+    pub async fn peek_local(&self, key: &str) -> Result<T> {
+        for store in &self.stores {
+            let store = store.read().await;
+            if matches!(*store, Storage::Remote(_)) {
+                continue;
+            }
+            if let Ok(data) = store.read(key).await {
+                return Ok(data);
+            }
+        }
+        bail!(DataStoreError::NotFound)
+    }
+
+    /// The objects, and bytes, held by each local tier, by name.
+    pub async fn usage(&self) -> Result<Vec<(String, Usage)>> {
+        let mut usage = Vec::with_capacity(self.stores.len());
+        for (name, store) in self.names.iter().zip(&self.stores) {
+            let store = store.read().await;
+            if matches!(*store, Storage::Remote(_)) {
+                continue;
+            }
+            usage.push((
+                name.to_owned(),
+                Usage {
+                    objects: store.len().await? as u64,
+                    bytes: store.size().await?,
+                },
+            ));
+        }
+        Ok(usage)
+    }
+
+    async fn recount(&self) -> Result<()> {
+        for listed in self.locate(&ListOptions::default()).await? {
+            if let Ok(value) = self.peek_local(&listed.key).await {
+                self.quotas.record(
+                    value.scope().as_ref(),
+                    1,
+                    DataType::serialize(&value).len() as i64,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases the quota held by a value, once it is no longer stored.
+    fn release(&self, value: &T) {
+        self.quotas.record(
+            value.scope().as_ref(),
+            -1,
+            -(DataType::serialize(value).len() as i64),
+        );
+    }
+
+    /// Deletes every value whose key the denylist forbids, returning how many.
+    async fn purge(&self) -> Result<usize> {
+        let mut purged = 0;
+        for key in self.list(&ListOptions::default()).await? {
+            if self.denylist.contains(&forbidden_as(&key)) {
+                self.forbid(&key).await?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Lists a page of keys across every local tier, along with the tiers holding each of them.
+    /// The network can't be enumerated, so isn't listed.
+    pub async fn locate(&self, options: &ListOptions) -> Result<Vec<Listed>> {
+        let mut listed: Vec<Listed> = Vec::new();
+        for (tier, store) in self.stores.iter().enumerate() {
+            let store = store.read().await;
+            if matches!(*store, Storage::Remote(_)) {
+                continue;
+            }
+            for key in store.list(options).await? {
+                match listed.binary_search_by(|listed| listed.key.cmp(&key)) {
+                    Ok(index) => listed[index].tiers.push(tier),
+                    Err(index) => listed.insert(
+                        index,
+                        Listed {
+                            key,
+                            tiers: vec![tier],
+                        },
+                    ),
+                }
+            }
+        }
+        // Each tier returns its own first page, so their union holds the first page of the model.
+        if let Some(limit) = options.limit {
+            listed.truncate(limit);
+        }
+        Ok(listed)
+    }
+}
+
 impl<T: DataType> Default for Model<T> {
     fn default() -> Self {
         Self {
-            stores: Vec::from([Storage::Memory(MemoryStorage::new(4096))]),
+            names: vec![Tier::Process.to_str().to_lowercase()],
+            stores: Vec::from([RwLock::new(Storage::Process(ProcessStorage::new(4096)))]),
+            notifier: Notifier::default(),
+            denylist: Default::default(),
+            keyring: Default::default(),
+            quotas: Default::default(),
         }
     }
 }
@@ -65,30 +403,184 @@ where
     T: serde::Serialize + serde::de::DeserializeOwned,
 {
     async fn read(&self, key: &str) -> Result<T> {
+        for (tier, store) in self.stores.iter().enumerate() {
+            let data = store.read().await.read(key).await;
+            if let Ok(data) = data {
+                if tier > 0 {
+                    let expires = store.read().await.expiry(key).await.unwrap_or(None);
+                    match self.place(key, &data, expires).await {
+                        Ok(()) => self.notifier.notify(Change::Promoted(key.to_owned())),
+                        Err(e) => debug!("Failed to promote {key:?} from tier {tier}: {e}"),
+                    }
+                }
+                return data.open(&self.keyring);
+            }
+        }
+        bail!(DataStoreError::NotFound)
+    }
+
+    /// Reads the value, as serialized, from the first tier holding it, as a view of that tier's
+    /// copy where it can, e.g. of a memory-mapped file, without promoting it. Sealed values are
+    /// opened, which copies them.
+    async fn read_bytes(&self, key: &str) -> Result<Bytes> {
         for store in &self.stores {
-            if let Ok(data) = store.read(key).await {
+            let Ok(data) = store.read().await.read_bytes(key).await else {
+                continue;
+            };
+            if !T::is_sealed(&data) {
                 return Ok(data);
             }
+            let value = <T as DataType>::deserialize(&data)?.open(&self.keyring)?;
+            return Ok(DataType::serialize(&value).into());
         }
         bail!(DataStoreError::NotFound)
     }
 
     async fn write(&mut self, key: &str, data: &T) -> Result<()> {
-        // let mut errors = vec![];
-        // Should handle migration to lower store, if store is full, and eviction if necessary
-        //   if let Err(err) = store.write(key, data).await {
-        //       bail!(DataStoreError::EWRITE)
-        //   }
-        todo!()
+        self.put(key, data).await
     }
 
     async fn delete(&mut self, key: &str) -> Result<()> {
-        // Is it worth migrating data up a tier?
-        todo!()
+        let previous = self.peek_local(key).await;
+        let mut found = false;
+        let mut error = None;
+        for store in self.stores.iter_mut() {
+            match store.get_mut().delete(key).await {
+                Ok(()) => found = true,
+                Err(e) => match e.downcast_ref::<DataStoreError>() {
+                    Some(DataStoreError::NotFound) => {}
+                    _ => error = error.or(Some(e)),
+                },
+            }
+        }
+        if let Some(error) = error {
+            return Err(error);
+        }
+        if !found {
+            bail!(DataStoreError::NotFound);
+        }
+        if let Ok(previous) = previous {
+            self.release(&previous);
+        }
+        self.notifier.notify(Change::Deleted(key.to_owned()));
+        Ok(())
+    }
+
+    async fn list(&self, options: &ListOptions) -> Result<Vec<String>> {
+        Ok(self
+            .locate(options)
+            .await?
+            .into_iter()
+            .map(|listed| listed.key)
+            .collect())
+    }
+
+    /// Whether a local tier holds the value, so without asking the network for providers.
+    async fn contains(&self, key: &str) -> Result<bool> {
+        for store in &self.stores {
+            let store = store.read().await;
+            if matches!(*store, Storage::Remote(_)) {
+                continue;
+            }
+            if store.contains(key).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
-    async fn list(&self, key: Option<&str>) -> Result<Vec<&str>> {
-        // Should zip all stores and return a list of keys
-        todo!()
+    async fn len(&self) -> Result<usize> {
+        let mut len = 0;
+        for store in &self.stores {
+            let store = store.read().await;
+            if !matches!(*store, Storage::Remote(_)) {
+                len += store.len().await?;
+            }
+        }
+        Ok(len)
+    }
+
+    async fn size(&self) -> Result<u64> {
+        let mut size = 0;
+        for store in &self.stores {
+            let store = store.read().await;
+            if !matches!(*store, Storage::Remote(_)) {
+                size += store.size().await?;
+            }
+        }
+        Ok(size)
+    }
+
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        for store in &self.stores {
+            store.read().await.extend_filter(filter).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_change(&self) -> Result<Changes> {
+        Ok(self.notifier.subscribe())
+    }
+
+    async fn forbid(&self, key: &str) -> Result<()> {
+        self.denylist.insert(&forbidden_as(key))?;
+        if let Ok(previous) = self.peek_local(key).await {
+            self.release(&previous);
+        }
+        for store in &self.stores {
+            let _ = store.write().await.delete(key).await;
+        }
+        self.notifier.notify(Change::Forbidden(key.to_owned()));
+        Ok(())
+    }
+
+    async fn allow(&self, key: &str) -> Result<()> {
+        self.denylist.remove(&forbidden_as(key))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{DataKey, Eviction};
+
+    /// A model of in-process tiers, each holding the given number of blocks of a single chunk,
+    /// oldest out first.
+    fn model(capacities: &[usize]) -> Model<Block> {
+        let size = Block::Bytes(vec![[0; 32]]).serialize().len();
+        let tiers = capacities
+            .iter()
+            .map(|capacity| TierConfig {
+                max_size: Some(capacity * size),
+                eviction: Some(Eviction::Fifo),
+                ..Tier::Process.into()
+            })
+            .collect();
+        Model::new(
+            &tiers,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn promoted_value_is_counted_once() {
+        let mut model = model(&[2, 2]);
+        let blocks: Vec<Block> = (0..4).map(|i| Block::Bytes(vec![[i; 32]])).collect();
+        for block in &blocks[..3] {
+            model.write(&block.key(), block).await.unwrap();
+        }
+        // Promoted to the first tier, while still held in the second.
+        model.read(&blocks[0].key()).await.unwrap();
+        // Evicted from the second tier, though still held in the first.
+        model.write(&blocks[3].key(), &blocks[3]).await.unwrap();
+
+        for block in &blocks {
+            assert!(model.peek_local(&block.key()).await.is_ok());
+        }
+        assert_eq!(model.quotas.usage(None).objects, 4);
     }
 }
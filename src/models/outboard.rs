@@ -0,0 +1,513 @@
+use anyhow::{bail, Result};
+use blake3::{
+    hazmat::{
+        left_subtree_len, merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt,
+        Mode,
+    },
+    Hasher, CHUNK_LEN, OUT_LEN,
+};
+use bytes::Bytes;
+use ciborium::{from_reader, into_writer};
+use futures::{channel::mpsc::Receiver, StreamExt};
+use std::ops::Range;
+use tracing::debug;
+
+use super::{Block, Models};
+use crate::{
+    common::{BlockRequest, BlockResponse, Event},
+    hash::{Hash, HashOpts},
+    node::Client,
+    storage::{DataKey, DataStore, DataStoreError, DataType},
+};
+
+/// The length of a parent node, i.e. the chaining values of its two children.
+const PARENT_LEN: usize = 2 * OUT_LEN;
+
+/// The tree blake3 hashes content as, held apart from the content, in the manner of Bao.
+///
+/// blake3 splits content into chunks of `CHUNK_LEN` bytes, and hashes them as the leaves of a
+/// binary tree, so the hash of a `Block::Bytes` is also the root of a Merkle tree over its
+/// canonical encoding, i.e. its tag, then its content. The outboard holds the parent nodes of that
+/// tree, so that any range of the encoding can be sent along with the nodes on its way to the
+/// root, and verified against the hash alone.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Outboard {
+    hash: Hash,
+    len: u64,
+    /// The parent nodes, in pre-order, i.e. each node before its left subtree, and that before
+    /// its right.
+    parents: Bytes,
+}
+
+/// A range of content, along with the proof of it, as the nodes of the tree covering it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Slice {
+    /// The length of the whole content, which shapes the tree.
+    len: u64,
+    /// The range asked for. The slice holds every chunk overlapping it.
+    range: Range<u64>,
+    /// The parent nodes on the way to the range, and its chunks, in pre-order.
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+enum Node {
+    Parent(Bytes),
+    Chunk(Bytes),
+}
+
+/// Verifies content as it is streamed, in slices, from the start, against its hash.
+///
+/// Each parent node is checked before the nodes below it, and each chunk as it is reached, so bad
+/// data is rejected at the first chunk it touches. The length of the content is only vouched for
+/// once its last chunk is verified, which `finish` requires.
+#[derive(Debug)]
+pub struct Verifier {
+    root: ChainingValue,
+    key: Option<[u8; OUT_LEN]>,
+    len: u64,
+    content: Vec<u8>,
+    complete: bool,
+}
+
+impl Outboard {
+    /// Builds the tree of the content, keyed by the scope of the hash it is addressed by, if any.
+    pub fn new(content: &[u8], key: Option<&Hash>) -> Self {
+        let secret = key.map(|key| *key.as_bytes());
+        let len = content.len() as u64;
+        let mut parents = Vec::with_capacity(parent_count(len) as usize * PARENT_LEN);
+        let root = build(content, 0, true, secret.as_ref(), &mut parents);
+        Outboard {
+            hash: Hash::from_bytes(&root, Some(HashOpts { key: key.cloned() })),
+            len,
+            parents: parents.into(),
+        }
+    }
+
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    /// The length of the content the tree is over.
+    pub fn content_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Cuts the range out of the content, along with its proof, clamping it to the content.
+    ///
+    /// Fails with `Invalid` if the range is empty, or past the end, and with `Corrupt` if the
+    /// content isn't the one the tree is over.
+    pub fn slice(&self, content: &Bytes, range: Range<u64>) -> Result<Slice> {
+        if content.len() as u64 != self.len {
+            bail!(DataStoreError::Corrupt);
+        }
+        let range = range.start..range.end.min(self.len);
+        // Empty content is a single, empty, chunk, which any range starting at zero covers.
+        if range.start >= range.end && !(self.len == 0 && range.start == 0) {
+            bail!(DataStoreError::Invalid);
+        }
+        let mut nodes = Vec::new();
+        self.extract(content, 0, self.len, &range, &mut 0, &mut nodes);
+        Ok(Slice {
+            len: self.len,
+            range,
+            nodes,
+        })
+    }
+
+    fn extract(
+        &self,
+        content: &Bytes,
+        offset: u64,
+        len: u64,
+        range: &Range<u64>,
+        cursor: &mut usize,
+        nodes: &mut Vec<Node>,
+    ) {
+        if len <= CHUNK_LEN as u64 {
+            nodes.push(Node::Chunk(
+                content.slice(offset as usize..(offset + len) as usize),
+            ));
+            return;
+        }
+        nodes.push(Node::Parent(
+            self.parents
+                .slice(*cursor * PARENT_LEN..(*cursor + 1) * PARENT_LEN),
+        ));
+        *cursor += 1;
+        let mid = left_subtree_len(len);
+        for (offset, len) in [(offset, mid), (offset + mid, len - mid)] {
+            if overlaps(range, offset, len) {
+                self.extract(content, offset, len, range, cursor, nodes);
+            } else {
+                *cursor += parent_count(len) as usize;
+            }
+        }
+    }
+}
+
+impl Slice {
+    /// The length of the whole content, not just of the slice.
+    pub fn content_len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn range(&self) -> &Range<u64> {
+        &self.range
+    }
+}
+
+impl Verifier {
+    /// Verifies content of the given length against the hash, keyed as its outboard was built,
+    /// which isn't told by the scope of the hash it is requested by.
+    pub fn new(hash: &Hash, len: u64, key: Option<&Hash>) -> Self {
+        Verifier {
+            root: *hash.as_bytes(),
+            key: key.map(|key| *key.as_bytes()),
+            len,
+            content: Vec::new(),
+            complete: false,
+        }
+    }
+
+    /// How much of the content is verified, from its start, which is where the next slice must
+    /// start.
+    pub fn verified(&self) -> u64 {
+        self.content.len() as u64
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Verifies the next slice of the content, keeping every chunk verified before any failure.
+    ///
+    /// Fails with `Corrupt` if the slice doesn't carry on from the content verified so far, or
+    /// if any node of it doesn't match the hash.
+    pub fn verify(&mut self, slice: &Slice) -> Result<()> {
+        if self.complete
+            || slice.len != self.len
+            || slice.range.start != self.verified()
+            || (slice.range.start >= slice.range.end && self.len > 0)
+        {
+            bail!(DataStoreError::Corrupt);
+        }
+        let mut nodes = slice.nodes.iter();
+        let root = self.root;
+        self.check(&mut nodes, 0, self.len, &slice.range, &root, true)?;
+        if nodes.next().is_some() {
+            bail!(DataStoreError::Corrupt);
+        }
+        self.complete = self.verified() == self.len;
+        Ok(())
+    }
+
+    /// The verified content, failing with `Corrupt` if some of it is still missing.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        if !self.complete {
+            bail!(DataStoreError::Corrupt);
+        }
+        Ok(self.content)
+    }
+
+    fn check<'a, I: Iterator<Item = &'a Node>>(
+        &mut self,
+        nodes: &mut I,
+        offset: u64,
+        len: u64,
+        range: &Range<u64>,
+        expected: &ChainingValue,
+        root: bool,
+    ) -> Result<()> {
+        let key = self.key;
+        match nodes.next() {
+            Some(Node::Chunk(chunk)) if len <= CHUNK_LEN as u64 => {
+                if chunk.len() as u64 != len
+                    || chunk_cv(chunk, offset, root, key.as_ref()) != *expected
+                {
+                    bail!(DataStoreError::Corrupt);
+                }
+                self.content.extend_from_slice(chunk);
+                Ok(())
+            }
+            Some(Node::Parent(parent)) if len > CHUNK_LEN as u64 => {
+                let Some((left, right)) = split(parent) else {
+                    bail!(DataStoreError::Corrupt);
+                };
+                if parent_cv(&left, &right, root, key.as_ref()) != *expected {
+                    bail!(DataStoreError::Corrupt);
+                }
+                let mid = left_subtree_len(len);
+                for (offset, len, expected) in
+                    [(offset, mid, left), (offset + mid, len - mid, right)]
+                {
+                    if overlaps(range, offset, len) {
+                        self.check(nodes, offset, len, range, &expected, false)?;
+                    }
+                }
+                Ok(())
+            }
+            _ => bail!(DataStoreError::Corrupt),
+        }
+    }
+}
+
+impl DataKey for Outboard {
+    fn key(&self) -> String {
+        self.hash.to_hex()
+    }
+}
+
+impl DataType for Outboard {
+    const NAMESPACE: &'static str = "outboards";
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        into_writer(self, &mut encoded).expect("Failed to serialize Outboard");
+        encoded
+    }
+
+    /// Fails with `Decode` if the data isn't an outboard, or holds the wrong number of nodes.
+    fn deserialize(data: &[u8]) -> Result<Self> {
+        let outboard: Outboard = from_reader(data).map_err(|_| DataStoreError::Decode)?;
+        if outboard.parents.len() != parent_count(outboard.len) as usize * PARENT_LEN {
+            bail!(DataStoreError::Decode);
+        }
+        Ok(outboard)
+    }
+}
+
+impl Models {
+    /// Answers a request for a block, with a slice of its content, and the proof of it, if it is a
+    /// `Block::Bytes` and a range is asked for, or with the whole block, as stored, otherwise.
+    pub async fn respond(&self, request: &BlockRequest) -> Result<BlockResponse> {
+        let key = request.hash.to_hex();
+        let block = self.blocks.peek_local(&key).await?;
+        match (&request.range, &block) {
+            (Some(range), Block::Bytes(_)) => {
+                let content = Bytes::from(block.canonical());
+                let outboard = self.outboard_of(&key, &content).await?;
+                Ok(BlockResponse::Slice(
+                    outboard.slice(&content, range.to_owned())?,
+                ))
+            }
+            _ => Ok(BlockResponse::new(block)),
+        }
+    }
+
+    /// Answers the requests of peers, as the node receives them, until it stops.
+    pub async fn serve(&self, mut events: Receiver<Event>, mut client: Client) {
+        while let Some(Event::InboundRequest { request, channel }) = events.next().await {
+            match self.respond(&request).await {
+                Ok(response) => client.respond_block(response, channel).await,
+                // Dropping the response channel refuses the request.
+                Err(e) => debug!("Not serving {:?}: {e}", request.inner()),
+            }
+        }
+    }
+
+    /// The outboard of a `Block::Bytes`, as stored alongside it, or built, and stored, if missing.
+    pub async fn outboard(&self, key: &str) -> Result<Outboard> {
+        let block = self.blocks.peek_local(key).await?;
+        if !matches!(block, Block::Bytes(_)) {
+            bail!(DataStoreError::Unsupported);
+        }
+        self.outboard_of(key, &block.canonical()).await
+    }
+
+    /// Stores the outboard of every `Block::Bytes` among the blocks. Outboards can always be
+    /// rebuilt from their block, so failing to store one fails nothing.
+    pub(super) async fn put_outboards(&self, blocks: &[Block]) {
+        for block in blocks {
+            if matches!(block, Block::Bytes(_)) {
+                let _ = self.outboard_of(&block.key(), &block.canonical()).await;
+            }
+        }
+    }
+
+    async fn outboard_of(&self, key: &str, content: &[u8]) -> Result<Outboard> {
+        if let Ok(outboard) = self.outboards.peek_local(key).await {
+            if outboard.content_len() == content.len() as u64 {
+                return Ok(outboard);
+            }
+        }
+        let outboard = Outboard::new(content, None);
+        if let Err(e) = self.outboards.put(key, &outboard).await {
+            debug!("Failed to store the outboard of {key:?}: {e}");
+        }
+        Ok(outboard)
+    }
+}
+
+/// Hashes the subtree of the content starting at the offset, writing its parent nodes, in
+/// pre-order, and returning its chaining value, or the root hash, if it is the whole tree.
+fn build(
+    content: &[u8],
+    offset: u64,
+    root: bool,
+    key: Option<&[u8; OUT_LEN]>,
+    parents: &mut Vec<u8>,
+) -> ChainingValue {
+    if content.len() <= CHUNK_LEN {
+        return chunk_cv(content, offset, root, key);
+    }
+    let at = parents.len();
+    parents.extend_from_slice(&[0; PARENT_LEN]);
+    let mid = left_subtree_len(content.len() as u64) as usize;
+    let left = build(&content[..mid], offset, false, key, parents);
+    let right = build(&content[mid..], offset + mid as u64, false, key, parents);
+    parents[at..at + OUT_LEN].copy_from_slice(&left);
+    parents[at + OUT_LEN..at + PARENT_LEN].copy_from_slice(&right);
+    parent_cv(&left, &right, root, key)
+}
+
+fn chunk_cv(chunk: &[u8], offset: u64, root: bool, key: Option<&[u8; OUT_LEN]>) -> ChainingValue {
+    let mut hasher = key.map_or_else(Hasher::new, Hasher::new_keyed);
+    if root {
+        return *hasher.update(chunk).finalize().as_bytes();
+    }
+    hasher
+        .set_input_offset(offset)
+        .update(chunk)
+        .finalize_non_root()
+}
+
+fn parent_cv(
+    left: &ChainingValue,
+    right: &ChainingValue,
+    root: bool,
+    key: Option<&[u8; OUT_LEN]>,
+) -> ChainingValue {
+    let mode = key.map_or(Mode::Hash, Mode::KeyedHash);
+    if root {
+        *merge_subtrees_root(left, right, mode).as_bytes()
+    } else {
+        merge_subtrees_non_root(left, right, mode)
+    }
+}
+
+fn split(parent: &[u8]) -> Option<(ChainingValue, ChainingValue)> {
+    if parent.len() != PARENT_LEN {
+        return None;
+    }
+    let (left, right) = parent.split_at(OUT_LEN);
+    Some((left.try_into().ok()?, right.try_into().ok()?))
+}
+
+/// The number of parent nodes in the tree of content of the given length, one fewer than its
+/// chunks. Empty content is a single, empty, chunk.
+fn parent_count(len: u64) -> u64 {
+    len.div_ceil(CHUNK_LEN as u64).max(1) - 1
+}
+
+fn overlaps(range: &Range<u64>, offset: u64, len: u64) -> bool {
+    range.start < offset + len && range.end > offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Content spanning several chunks, with a partial last one.
+    fn content() -> Bytes {
+        (0..5 * CHUNK_LEN as u32 + 7)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>()
+            .into()
+    }
+
+    /// Streams the content through a verifier, in slices of the given length.
+    fn stream(outboard: &Outboard, content: &Bytes, verifier: &mut Verifier, step: u64) {
+        while !verifier.is_complete() {
+            let start = verifier.verified();
+            let slice = outboard.slice(content, start..start + step).unwrap();
+            verifier.verify(&slice).unwrap();
+        }
+    }
+
+    #[test]
+    fn slices_verify_against_the_hash() {
+        let content = content();
+        let outboard = Outboard::new(&content, None);
+        assert_eq!(
+            outboard.hash().as_bytes(),
+            blake3::hash(&content).as_bytes()
+        );
+
+        let mut verifier = Verifier::new(outboard.hash(), outboard.content_len(), None);
+        stream(&outboard, &content, &mut verifier, 2 * CHUNK_LEN as u64);
+        assert_eq!(verifier.finish().unwrap(), content);
+    }
+
+    #[test]
+    fn scoped_request_verifies_unkeyed_tree() {
+        let content = content();
+        let outboard = Outboard::new(&content, None);
+        let scoped = Hash::from_bytes(
+            outboard.hash().as_bytes(),
+            Some(HashOpts {
+                key: Some(Hash::new(b"scope", None)),
+            }),
+        );
+
+        let mut verifier = Verifier::new(&scoped, outboard.content_len(), None);
+        stream(&outboard, &content, &mut verifier, CHUNK_LEN as u64);
+        assert_eq!(verifier.finish().unwrap(), content);
+    }
+
+    #[test]
+    fn keyed_tree_verifies_with_its_key() {
+        let content = content();
+        let key = Hash::new(b"scope", None);
+        let outboard = Outboard::new(&content, Some(&key));
+        assert_eq!(
+            outboard.hash().as_bytes(),
+            blake3::keyed_hash(key.as_bytes(), &content).as_bytes()
+        );
+
+        let mut verifier = Verifier::new(outboard.hash(), outboard.content_len(), Some(&key));
+        stream(&outboard, &content, &mut verifier, 3 * CHUNK_LEN as u64);
+        assert_eq!(verifier.finish().unwrap(), content);
+
+        let slice = outboard.slice(&content, 0..1).unwrap();
+        let mut unkeyed = Verifier::new(outboard.hash(), outboard.content_len(), None);
+        assert!(unkeyed.verify(&slice).is_err());
+    }
+
+    #[test]
+    fn tampered_slice_is_rejected_at_its_bad_chunk() {
+        let content = content();
+        let outboard = Outboard::new(&content, None);
+        let mut slice = outboard.slice(&content, 0..content.len() as u64).unwrap();
+        let Some(Node::Chunk(chunk)) = slice
+            .nodes
+            .iter_mut()
+            .filter(|node| matches!(node, Node::Chunk(_)))
+            .nth(2)
+        else {
+            unreachable!();
+        };
+        let mut flipped = chunk.to_vec();
+        flipped[0] ^= 1;
+        *chunk = flipped.into();
+
+        let mut verifier = Verifier::new(outboard.hash(), outboard.content_len(), None);
+        assert!(verifier.verify(&slice).is_err());
+        // The chunks before the bad one were verified, and kept.
+        assert_eq!(verifier.verified(), 2 * CHUNK_LEN as u64);
+        assert!(!verifier.is_complete());
+    }
+
+    #[test]
+    fn slice_out_of_order_is_rejected() {
+        let content = content();
+        let outboard = Outboard::new(&content, None);
+        let slice = outboard
+            .slice(&content, CHUNK_LEN as u64..2 * CHUNK_LEN as u64)
+            .unwrap();
+
+        let mut verifier = Verifier::new(outboard.hash(), outboard.content_len(), None);
+        assert!(verifier.verify(&slice).is_err());
+        assert!(Verifier::new(outboard.hash(), 1, None).finish().is_err());
+    }
+}
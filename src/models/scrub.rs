@@ -0,0 +1,242 @@
+use anyhow::Result;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use super::{Model, Models};
+use crate::storage::{DataKey, DataStore, DataStoreError, DataType, ListOptions, Storage};
+
+/// The outcome of a scrub, with keys formatted as `tier:key`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// Values read back, and re-hashed.
+    pub checked: usize,
+    /// Values whose contents no longer match their key, and so were quarantined.
+    pub quarantined: Vec<String>,
+    /// Quarantined values restored from a good copy in another tier, or the network.
+    pub repaired: Vec<String>,
+    /// Quarantined values without a good copy anywhere.
+    pub unrepaired: Vec<String>,
+    /// Values which couldn't be checked, e.g. as their tier failed to read them, so were left as
+    /// they are, or `tier:*` for a tier which couldn't be listed at all.
+    pub skipped: Vec<String>,
+}
+
+impl ScrubReport {
+    pub fn merge(&mut self, other: ScrubReport) {
+        self.checked += other.checked;
+        self.quarantined.extend(other.quarantined);
+        self.repaired.extend(other.repaired);
+        self.unrepaired.extend(other.unrepaired);
+        self.skipped.extend(other.skipped);
+    }
+}
+
+impl<T: DataType> Model<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Reads back every value in every tier, checking it still hashes to its key.
+    ///
+    /// Corrupt values are quarantined, then repaired from the first other tier holding a good
+    /// copy, which includes the network when a remote tier is configured. So are files which
+    /// can't be read far enough to tell their key, though those can't be repaired. A tier which
+    /// fails in any other way is reported, and skipped, rather than failing the scrub.
+    pub async fn scrub(&self) -> Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+        for (tier, store) in self.stores.iter().enumerate() {
+            // The network holds no bytes of its own to check.
+            if matches!(*store.read().await, Storage::Remote(_)) {
+                continue;
+            }
+            match store.write().await.quarantine_unreadable().await {
+                Ok(unreadable) => {
+                    for path in unreadable {
+                        warn!("Tier {tier} held {path:?}, which can't be read");
+                        report.quarantined.push(format!("{tier}:{path}"));
+                        report.unrepaired.push(format!("{tier}:{path}"));
+                    }
+                }
+                Err(e) => error!("Failed to quarantine the unreadable values of tier {tier}: {e}"),
+            }
+            let keys = match store.read().await.list(&ListOptions::default()).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    error!("Failed to list tier {tier}, skipping it: {e}");
+                    report.skipped.push(format!("{tier}:*"));
+                    continue;
+                }
+            };
+            for key in keys {
+                report.checked += 1;
+                match self.verify(tier, &key).await {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(e) => {
+                        warn!("Failed to check {key:?} in tier {tier}, skipping it: {e}");
+                        report.skipped.push(format!("{tier}:{key}"));
+                        continue;
+                    }
+                }
+
+                warn!("Tier {tier} holds a corrupt copy of {key:?}");
+                if let Err(e) = store.write().await.quarantine(&key).await {
+                    error!("Failed to quarantine {key:?} in tier {tier}: {e}");
+                    report.skipped.push(format!("{tier}:{key}"));
+                    continue;
+                }
+                report.quarantined.push(format!("{tier}:{key}"));
+
+                match self.repair(tier, &key).await {
+                    Ok(source) => {
+                        info!("Repaired {key:?} in tier {tier}, from tier {source}");
+                        report.repaired.push(format!("{tier}:{key}"));
+                    }
+                    Err(e) => {
+                        error!("Failed to repair {key:?} in tier {tier}: {e}");
+                        report.unrepaired.push(format!("{tier}:{key}"));
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns whether the value the tier holds still hashes to the given key, recomputed from
+    /// its stored bytes. A value which has since gone is fine, while one which doesn't decode, or
+    /// fails a checksum, isn't. Any other failure, e.g. of I/O, says nothing of the value, so is
+    /// returned.
+    async fn verify(&self, tier: usize, key: &str) -> Result<bool> {
+        match self.stores[tier].read().await.read(key).await {
+            Ok(value) => Ok(value.is_intact(key, &self.keyring)),
+            Err(e) => match DataStoreError::of(&e) {
+                Some(DataStoreError::NotFound) => Ok(true),
+                Some(DataStoreError::Decode | DataStoreError::Corrupt) => Ok(false),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Copies a good value into the tier, returning the tier it came from.
+    async fn repair(&self, tier: usize, key: &str) -> Result<usize> {
+        for (source, store) in self.stores.iter().enumerate() {
+            if source == tier {
+                continue;
+            }
+            let value = match store.read().await.read(key).await {
+                Ok(value) if value.is_intact(key, &self.keyring) => value,
+                Ok(_) => continue,
+                Err(e) => {
+                    debug!("Tier {source} can't repair {key:?}: {e}");
+                    continue;
+                }
+            };
+            let expires = store.read().await.expiry(key).await.unwrap_or(None);
+            self.stores[tier]
+                .write()
+                .await
+                .insert(key, &value, expires)
+                .await?;
+            return Ok(source);
+        }
+        Err(DataStoreError::NotFound.into())
+    }
+}
+
+impl Models {
+    /// Scrubs the blocks, and entries, returning a summary of both.
+    pub async fn scrub(&self) -> Result<ScrubReport> {
+        let mut report = self.blocks.scrub().await?;
+        report.merge(self.entries.scrub().await?);
+        info!(
+            "Scrubbed {} values, {} quarantined, {} repaired, {} unrepaired, {} skipped",
+            report.checked,
+            report.quarantined.len(),
+            report.repaired.len(),
+            report.unrepaired.len(),
+            report.skipped.len()
+        );
+        Ok(report)
+    }
+
+    /// Scrubs in the background, once every period, for as long as the future is polled.
+    pub async fn scrub_every(&self, period: Duration) {
+        loop {
+            futures_timer::Delay::new(period).await;
+            if let Err(e) = self.scrub().await {
+                error!("Scrub failed: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Block;
+    use crate::storage::{Tier, TierConfig};
+
+    fn model(tiers: Vec<TierConfig>) -> Model<Block> {
+        Model::new(
+            &tiers,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn corrupt_copy_is_quarantined_then_repaired() {
+        let model = model(vec![Tier::Process.into(), Tier::Process.into()]);
+        let good = Block::Bytes(vec![[1; 32]]);
+        let key = good.key();
+        let corrupt = Block::Bytes(vec![[2; 32]]);
+        let stores = &model.stores;
+        stores[0].write().await.write(&key, &corrupt).await.unwrap();
+        stores[1].write().await.write(&key, &good).await.unwrap();
+
+        let report = model.scrub().await.unwrap();
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.quarantined, [format!("0:{key}")]);
+        assert_eq!(report.repaired, [format!("0:{key}")]);
+        assert!(report.unrepaired.is_empty());
+        assert_eq!(stores[0].read().await.read(&key).await.unwrap(), good);
+    }
+
+    #[tokio::test]
+    async fn corrupt_copy_without_a_good_one_is_unrepaired() {
+        let model = model(vec![Tier::Process.into(), Tier::Process.into()]);
+        let key = Block::Bytes(vec![[1; 32]]).key();
+        let corrupt = Block::Bytes(vec![[2; 32]]);
+        model.stores[0]
+            .write()
+            .await
+            .write(&key, &corrupt)
+            .await
+            .unwrap();
+
+        let report = model.scrub().await.unwrap();
+        assert_eq!(report.quarantined, [format!("0:{key}")]);
+        assert_eq!(report.unrepaired, [format!("0:{key}")]);
+        assert!(!model.stores[0].read().await.contains(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unreadable_file_is_quarantined_without_failing_the_scrub() {
+        let dir = std::env::temp_dir().join(format!("gra-scrub-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let model = model(vec![Tier::Disk(dir.clone().into()).into()]);
+        let block = Block::Bytes(vec![[1; 32]]);
+        model.put(&block.key(), &block).await.unwrap();
+        let shard = dir.join(Block::NAMESPACE).join("00").join("00");
+        std::fs::create_dir_all(&shard).unwrap();
+        std::fs::write(shard.join("00"), b"not an envelope").unwrap();
+
+        let report = model.scrub().await.unwrap();
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.quarantined.len(), 1);
+        assert_eq!(report.unrepaired, report.quarantined);
+        assert!(report.repaired.is_empty());
+        assert_eq!(model.len().await.unwrap(), 1);
+    }
+}
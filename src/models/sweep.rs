@@ -0,0 +1,56 @@
+use anyhow::Result;
+use hashbrown::HashMap;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+use super::{Model, Models};
+use crate::storage::{Change, DataStore, DataType};
+
+impl<T: DataType> Model<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Removes every expired value from every tier, returning the keys removed.
+    pub async fn sweep(&self) -> Result<Vec<String>> {
+        let mut swept: HashMap<String, T> = HashMap::new();
+        for (tier, store) in self.stores.iter().enumerate() {
+            let expired = store.write().await.sweep().await?;
+            debug!("Swept {} expired values from tier {tier}", expired.len());
+            swept.extend(expired);
+        }
+
+        let mut keys = Vec::with_capacity(swept.len());
+        for (key, value) in swept {
+            // Tiers share an expiry, so a value is only still held if it was rewritten meanwhile.
+            if self.peek_local(&key).await.is_err() {
+                self.release(&value);
+                self.notifier.notify(Change::Expired(key.to_owned()));
+            }
+            keys.push(key);
+        }
+        keys.sort_unstable();
+        Ok(keys)
+    }
+}
+
+impl Models {
+    /// Removes every expired block, and entry, returning the keys removed.
+    pub async fn sweep(&self) -> Result<Vec<String>> {
+        let mut swept = self.blocks.sweep().await?;
+        swept.extend(self.entries.sweep().await?);
+        if !swept.is_empty() {
+            info!("Swept {} expired values", swept.len());
+        }
+        Ok(swept)
+    }
+
+    /// Sweeps in the background, once every period, for as long as the future is polled.
+    pub async fn sweep_every(&self, period: Duration) {
+        loop {
+            futures_timer::Delay::new(period).await;
+            if let Err(e) = self.sweep().await {
+                error!("Sweep failed: {e}");
+            }
+        }
+    }
+}
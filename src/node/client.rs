@@ -4,22 +4,28 @@ use futures::prelude::*;
 use futures::StreamExt;
 use libp2p::{request_response::ResponseChannel, Multiaddr, PeerId};
 use std::collections::HashSet;
+use std::sync::Arc;
 use tracing::{debug, info};
 
 use crate::common::BlockResponse;
-use crate::hash::Hash;
-use crate::models::Block;
+use crate::hash::{CustomHash, Hash, KeyVersion};
+use crate::models::{Block, Verifier};
+use crate::storage::{Change, Changes, DataStoreError, Denylist};
 
 use super::command::Command;
 
-#[derive(Clone)]
+/// The most content asked of a peer at once.
+const SLICE_LEN: u64 = 64 * blake3::CHUNK_LEN as u64;
+
+#[derive(Debug, Clone)]
 pub struct Client {
     sender: mpsc::Sender<Command>,
+    denylist: Arc<Denylist>,
 }
 
 impl Client {
-    pub fn new(sender: mpsc::Sender<Command>) -> Self {
-        Self { sender }
+    pub fn new(sender: mpsc::Sender<Command>, denylist: Arc<Denylist>) -> Self {
+        Self { sender, denylist }
     }
 
     /// Listen for incoming connections on the given address.
@@ -63,21 +69,53 @@ impl Client {
         receiver.await.expect("Sender not to be dropped.");
     }
 
-    /// Find the providers for the given block on the DHT.
-    pub async fn get_providers(&mut self, hash: Hash) -> HashSet<PeerId> {
-        let (sender, receiver) = oneshot::channel();
-        debug!("Requesting providers for {:?}", hash);
+    /// Stop advertising the local node as a provider of the given block.
+    pub async fn stop_providing(&mut self, hash: Hash) {
         self.sender
-            .send(Command::GetProviders {
+            .send(Command::StopProviding {
                 hash: Hash::new(hash.as_bytes(), None),
-                sender,
             })
             .await
             .expect("Command receiver not to be dropped.");
-        debug!("Waiting for providers for {:?}", hash);
-        let peers = receiver.await.expect("Sender not to be dropped.");
-        debug!("Received providers for {:?}", hash);
-        peers
+    }
+
+    /// Advertise every block written to a store, for as long as the stream of changes lasts.
+    pub async fn announce(&mut self, mut changes: Changes) {
+        while let Some(change) = changes.next().await {
+            let Change::Written(key) = change else {
+                continue;
+            };
+            match key.parse() {
+                Ok(hash) => self.start_providing(hash).await,
+                Err(e) => debug!("Not announcing {key:?}: {e}"),
+            }
+        }
+    }
+
+    /// Find the providers for the given block on the DHT, under each key version in turn, newest
+    /// first, until some are found.
+    pub async fn get_providers(&mut self, hash: Hash) -> HashSet<PeerId> {
+        for version in KeyVersion::ALL {
+            let (sender, receiver) = oneshot::channel();
+            debug!(
+                "Requesting providers for {:?}, under {:?} keys",
+                hash, version
+            );
+            self.sender
+                .send(Command::GetProviders {
+                    hash: Hash::new(hash.as_bytes(), None),
+                    version,
+                    sender,
+                })
+                .await
+                .expect("Command receiver not to be dropped.");
+            let peers = receiver.await.expect("Sender not to be dropped.");
+            debug!("Received {} providers for {:?}", peers.len(), hash);
+            if !peers.is_empty() {
+                return peers;
+            }
+        }
+        HashSet::new()
     }
 
     /// Request the content of the given block from the given peer.
@@ -86,43 +124,77 @@ impl Client {
         hash: Hash,
         peers: Option<HashSet<PeerId>>,
     ) -> Result<Block> {
+        if self.denylist.contains(&hash.to_hex()) {
+            bail!(DataStoreError::AccessDenied);
+        }
         let peers = if let Some(peers) = peers {
             peers
         } else {
             self.get_providers(hash.clone()).await
         };
 
-        let result: Vec<Block> = Vec::new();
-
         for peer in peers {
+            match self.fetch_block(&hash, peer).await {
+                Ok(block) => return Ok(block),
+                Err(e) => debug!("Failed to fetch {:?} from {:?}: {:?}", hash, peer, e),
+            }
+        }
+        bail!("No block found for {:?}", hash);
+    }
+
+    /// Requests the block from a single peer, failing with `Corrupt` if it doesn't match the hash.
+    ///
+    /// The content of a `Block::Bytes` is streamed in slices, each verified, chunk by chunk, as it
+    /// arrives, so a peer sending bad data is dropped at its first bad chunk, rather than once all
+    /// of it is downloaded.
+    async fn fetch_block(&mut self, hash: &Hash, peer: PeerId) -> Result<Block> {
+        let mut verifier: Option<Verifier> = None;
+        loop {
+            let start = verifier.as_ref().map_or(0, Verifier::verified);
             let (sender, receiver) = oneshot::channel();
             self.sender
                 .send(Command::RequestBlock {
                     hash: hash.clone(),
+                    range: Some(start..start + SLICE_LEN),
                     peer,
                     sender,
                 })
                 .await
                 .expect("Command receiver not to be dropped.");
 
-            let res = receiver.await.expect("Sender not be dropped.");
+            match receiver.await.expect("Sender not be dropped.")? {
+                // Blocks without content to slice are small, so are sent, and checked, whole.
+                BlockResponse::Block(block) if verifier.is_none() => {
+                    if block.hash() != *hash.as_bytes() {
+                        bail!(DataStoreError::Corrupt);
+                    }
+                    return Ok(block);
+                }
+                BlockResponse::Block(_) => bail!(DataStoreError::Corrupt),
+                BlockResponse::Slice(slice) => {
+                    // Only a `Block::Bytes` is sliced, and its tree is never keyed, whatever the
+                    // scope of the hash it is asked for by.
+                    let mut current = verifier
+                        .take()
+                        .unwrap_or_else(|| Verifier::new(hash, slice.content_len(), None));
+                    current.verify(&slice)?;
+                    if current.is_complete() {
+                        return Block::from_chunks(&current.finish()?);
+                    }
+                    verifier = Some(current);
+                }
+            }
         }
-        // result.sort_by_cached_key(|block| block.);
-        if result.is_empty() {
-            bail!("No block found for {:?}", hash);
-        }
-        // TODO: Add logic, to select the best block from the list of results.
-        // TODO: Add logic to handle Block enum
-        Ok(result[0].clone())
     }
 
-    /// Respond with the provided block content to the given request.
-    pub async fn respond_block(&mut self, block: Vec<u8>, channel: ResponseChannel<BlockResponse>) {
+    /// Respond to the given request, e.g. with the answer of `Models::respond`.
+    pub async fn respond_block(
+        &mut self,
+        response: BlockResponse,
+        channel: ResponseChannel<BlockResponse>,
+    ) {
         self.sender
-            .send(Command::RespondBlock {
-                block: block.as_slice().into(),
-                channel,
-            })
+            .send(Command::RespondBlock { response, channel })
             .await
             .expect("Command receiver not to be dropped.");
     }
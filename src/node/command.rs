@@ -7,11 +7,11 @@ use libp2p::Swarm;
 use libp2p::{request_response::ResponseChannel, Multiaddr, PeerId};
 use std::collections::HashSet;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
 use tracing::info;
 
 use crate::common::{BlockRequest, BlockResponse};
-use crate::hash::Hash;
-use crate::models::Block;
+use crate::hash::{Hash, KeyVersion};
 use crate::node::Node;
 
 use super::Behaviour;
@@ -35,17 +35,22 @@ pub enum Command {
         hash: Hash,
         sender: oneshot::Sender<()>,
     },
+    StopProviding {
+        hash: Hash,
+    },
     GetProviders {
         hash: Hash,
+        version: KeyVersion,
         sender: oneshot::Sender<HashSet<PeerId>>,
     },
     RequestBlock {
         hash: Hash,
+        range: Option<Range<u64>>,
         peer: PeerId,
-        sender: oneshot::Sender<Result<Vec<u8>>>,
+        sender: oneshot::Sender<Result<BlockResponse>>,
     },
     RespondBlock {
-        block: Block,
+        response: BlockResponse,
         channel: ResponseChannel<BlockResponse>,
     },
 }
@@ -113,33 +118,58 @@ pub async fn handle(node: &mut Node, command: Command) {
                 todo!("Already dialing peer.");
             }
         }
+        // Provider records are published under every key version, so that older nodes, which
+        // only look up their own, still find them. Only the latest is awaited.
         Command::StartProviding { hash, sender } => {
+            let kad = &mut swarm.behaviour_mut().common.kad;
+            for version in KeyVersion::ALL {
+                if version != KeyVersion::LATEST {
+                    kad.start_providing(hash.record_key(version))
+                        .expect("No store error.");
+                }
+            }
+            let query_id = kad
+                .start_providing(hash.record_key(KeyVersion::LATEST))
+                .expect("No store error.");
+            node.pending.start_providing.insert(query_id, sender);
+        }
+        Command::StopProviding { hash } => {
+            let kad = &mut swarm.behaviour_mut().common.kad;
+            for version in KeyVersion::ALL {
+                kad.stop_providing(&hash.record_key(version));
+            }
+        }
+        Command::GetProviders {
+            hash,
+            version,
+            sender,
+        } => {
             let query_id = swarm
                 .behaviour_mut()
                 .common
                 .kad
-                .start_providing(hash.to_cbor().into())
-                .expect("No store error.");
-            node.pending.start_providing.insert(query_id, sender);
-        }
-        Command::GetProviders { hash, sender } => {
-            let query_id = swarm.behaviour_mut().common.kad.get_providers(hash.into());
+                .get_providers(hash.record_key(version));
             node.pending.get_providers.insert(query_id, sender);
         }
-        Command::RequestBlock { hash, peer, sender } => {
+        Command::RequestBlock {
+            hash,
+            range,
+            peer,
+            sender,
+        } => {
             let request_id = swarm
                 .behaviour_mut()
                 .common
                 .request_response
-                .send_request(&peer, BlockRequest { hash });
+                .send_request(&peer, BlockRequest { hash, range });
             node.pending.request_file.insert(request_id, sender);
         }
-        Command::RespondBlock { block, channel } => {
+        Command::RespondBlock { response, channel } => {
             swarm
                 .behaviour_mut()
                 .common
                 .request_response
-                .send_response(channel, BlockResponse::new(block))
+                .send_response(channel, response)
                 .expect("Connection to peer to be still open.");
         }
     }
@@ -1,11 +1,14 @@
 use super::{Behaviour, BehaviourEvent, Node};
-use tracing::{error, info};
+use anyhow::anyhow;
+use libp2p::{kad, request_response};
+use std::collections::HashSet;
+use tracing::{debug, error, info};
 
 use crate::common;
 
 use libp2p::{relay, Swarm};
 
-pub async fn handle(swarm: &mut Swarm<Behaviour>, event: BehaviourEvent) {
+pub async fn handle(node: &mut Node, event: BehaviourEvent) {
     match event {
         BehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted {
             relay_peer_id,
@@ -17,8 +20,34 @@ pub async fn handle(swarm: &mut Swarm<Behaviour>, event: BehaviourEvent) {
         BehaviourEvent::RelayClient(event) => {
             info!(?event)
         }
+        BehaviourEvent::Common(common::BehaviourEvent::RequestResponse(
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                ..
+            },
+        )) => {
+            if node.denylist.contains(&request.inner().to_hex()) {
+                // Dropping the response channel refuses the request.
+                info!("Refusing forbidden {:?} to {peer:?}", request.inner());
+                return;
+            }
+            // Never waited on, so a busy, or absent, server can't stall the swarm. The request is
+            // refused instead.
+            if let Err(e) = node
+                .event_sender
+                .try_send(common::Event::InboundRequest { request, channel })
+            {
+                debug!("Refusing request from {peer:?}: {e}");
+            }
+        }
         BehaviourEvent::Common(event) => {
-            common::event::handle(swarm, event).await;
+            if !resolve_pending(node, &event) {
+                common::event::handle(&mut node.swarm, event).await;
+            }
         }
         _ => {
             error!("Unhandled event: {event:?}");
@@ -26,3 +55,73 @@ pub async fn handle(swarm: &mut Swarm<Behaviour>, event: BehaviourEvent) {
         }
     };
 }
+
+/// Completes the queries, and requests, awaited by a `Client`. Returns true if the event was consumed.
+fn resolve_pending(node: &mut Node, event: &common::BehaviourEvent) -> bool {
+    match event {
+        common::BehaviourEvent::Kad(kad::Event::OutboundQueryProgressed { id, result, .. }) => {
+            match result {
+                kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders {
+                    providers,
+                    ..
+                })) => {
+                    if let Some(sender) = node.pending.get_providers.remove(id) {
+                        let _ = sender.send(providers.to_owned());
+                        // Finish the query. We are only interested in the first result.
+                        if let Some(mut query) = node.swarm.behaviour_mut().common.kad.query_mut(id)
+                        {
+                            query.finish();
+                        }
+                    }
+                    true
+                }
+                kad::QueryResult::GetProviders(result) => {
+                    if let Err(err) = result {
+                        error!("Failed to get providers: {err:?}");
+                    }
+                    if let Some(sender) = node.pending.get_providers.remove(id) {
+                        let _ = sender.send(HashSet::new());
+                    }
+                    true
+                }
+                kad::QueryResult::StartProviding(result) => {
+                    match result {
+                        Ok(kad::AddProviderOk { key }) => {
+                            info!("Successfully put provider record {:?}", key);
+                        }
+                        Err(err) => error!("Failed to put provider record: {err:?}"),
+                    }
+                    if let Some(sender) = node.pending.start_providing.remove(id) {
+                        let _ = sender.send(());
+                    }
+                    true
+                }
+                _ => false,
+            }
+        }
+        common::BehaviourEvent::RequestResponse(request_response::Event::Message {
+            message:
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                },
+            ..
+        }) => {
+            if let Some(sender) = node.pending.request_file.remove(request_id) {
+                let _ = sender.send(Ok(response.to_owned()));
+            }
+            true
+        }
+        common::BehaviourEvent::RequestResponse(request_response::Event::OutboundFailure {
+            request_id,
+            error,
+            ..
+        }) => {
+            if let Some(sender) = node.pending.request_file.remove(request_id) {
+                let _ = sender.send(Err(anyhow!("{error}")));
+            }
+            true
+        }
+        _ => false,
+    }
+}
@@ -1,12 +1,10 @@
 use anyhow::{anyhow, bail, Result};
 use async_std::task;
-use clap::{crate_name, crate_version};
 use futures::channel::mpsc::{self, Receiver, Sender};
 use futures::channel::oneshot;
 use futures::prelude::*;
 use futures::{FutureExt, StreamExt};
 use hashbrown::{hash_map, HashMap};
-use lazy_static::lazy_static;
 use libp2p::identity::Keypair;
 use libp2p::kad::{Record, RecordKey};
 use libp2p::multiaddr::Protocol;
@@ -28,6 +26,7 @@ use tracing::{debug, error, info, trace};
 use crate::common::{self, generate_identity, BlockRequest, BlockResponse};
 use crate::hash::Hash;
 use crate::models::Block;
+use crate::storage::Denylist;
 
 mod behaviour;
 use behaviour::{Behaviour, BehaviourEvent};
@@ -40,9 +39,8 @@ pub use client::Client;
 mod command;
 pub use command::Command;
 
-lazy_static! {
-    pub static ref PROTOCOL: String = format!("/gra/{}/{}", crate_name!(), crate_version!());
-}
+/// The most requests of peers queued for an answer, past which they are refused.
+const INBOUND_REQUESTS: usize = 32;
 
 #[repr(C)]
 pub struct Node {
@@ -53,7 +51,8 @@ pub struct Node {
     command_sender: Sender<command::Command>,
     command_receiver: Receiver<command::Command>,
     event_sender: Sender<common::Event>,
-    event_receiver: Receiver<common::Event>,
+    event_receiver: Option<Receiver<common::Event>>,
+    denylist: Arc<Denylist>,
     // TODO: Rename
     // TODO: Change to a more efficient data structure.
     pending: Pending,
@@ -67,7 +66,8 @@ pub struct Pending {
     dial: HashMap<PeerId, oneshot::Sender<Result<()>>>,
     start_providing: HashMap<kad::QueryId, oneshot::Sender<()>>,
     get_providers: HashMap<kad::QueryId, oneshot::Sender<HashSet<PeerId>>>,
-    request_file: HashMap<request_response::OutboundRequestId, oneshot::Sender<Result<Vec<u8>>>>,
+    request_file:
+        HashMap<request_response::OutboundRequestId, oneshot::Sender<Result<BlockResponse>>>,
 }
 
 impl fmt::Debug for Node {
@@ -88,6 +88,7 @@ impl Node {
         identity: Keypair,
         daemon_address: Option<Multiaddr>,
         bootnodes: Option<[&str; 1]>,
+        denylist: Arc<Denylist>,
     ) -> Result<Self> {
         debug!("Creating Node");
 
@@ -121,7 +122,7 @@ impl Node {
         }
 
         let (command_sender, command_receiver) = mpsc::channel(0);
-        let (event_sender, event_receiver) = mpsc::channel(0);
+        let (event_sender, event_receiver) = mpsc::channel(INBOUND_REQUESTS);
 
         Ok(Self {
             address,
@@ -131,7 +132,8 @@ impl Node {
             command_sender,
             command_receiver,
             event_sender,
-            event_receiver,
+            event_receiver: Some(event_receiver),
+            denylist,
             pending: Default::default(),
         })
     }
@@ -152,7 +154,13 @@ impl Node {
     }
 
     pub fn client(&self) -> Client {
-        Client::new(self.command_sender.clone())
+        Client::new(self.command_sender.clone(), self.denylist.clone())
+    }
+
+    /// The requests of peers, to be answered, e.g. by `Models::serve`. Only the first caller gets
+    /// them.
+    pub fn events(&mut self) -> Option<Receiver<common::Event>> {
+        self.event_receiver.take()
     }
 }
 
@@ -226,7 +234,7 @@ pub async fn handle_swarm_event(node: &mut Node, event: SwarmEvent<BehaviourEven
             peer_id: Some(peer_id),
             ..
         } => trace!("Dialing {peer_id}"),
-        SwarmEvent::Behaviour(event) => event::handle(&mut node.swarm, event).await,
+        SwarmEvent::Behaviour(event) => event::handle(node, event).await,
         e => info!("{e:?}"),
     };
 }
@@ -9,9 +9,11 @@ use std::{
 use tracing::{debug, info, trace};
 
 use crate::{
+    chunker::Chunker,
     hash::{Hash, HashOpts},
-    models::{Block, Entry, BLOCK_SIZE},
+    models::{Batch, Block, Chunking, Entry, BLOCK_SIZE},
     node::Node,
+    storage::{DataType, Keyring},
 };
 
 pub const BUF_SIZE: usize = 1024;
@@ -48,7 +50,12 @@ pub const BUF_SIZE: usize = 1024;
 
 // TODO: At least rename, or potential implement from/into
 /// Function to process a file, or a directory and return the path hash and its chunks
-pub fn add_path(path: &Path, scope: Option<Hash>) -> Result<Vec<Entry>> {
+pub fn add_path(
+    path: &Path,
+    scope: Option<Hash>,
+    chunker: Option<Chunker>,
+    keyring: &Keyring,
+) -> Result<Vec<Entry>> {
     let hash = Hash::new(
         path.to_string_lossy().as_bytes(),
         Some(HashOpts {
@@ -56,20 +63,146 @@ pub fn add_path(path: &Path, scope: Option<Hash>) -> Result<Vec<Entry>> {
         }),
     );
 
-    let entries: Vec<Entry> = if path.is_file() {
-        vec![process_file(path, scope.to_owned())?]
-    } else {
-        visit_dirs(path, scope.to_owned())?
-    };
+    let entries: Vec<Entry> = visit_path(path, scope, chunker, keyring)?
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .collect();
 
     Ok(entries)
 }
 
-fn process_file(path: &Path, scope: Option<Hash>) -> Result<Entry> {
+/// Stages the entries for a file, or a directory, along with the blocks they refer to, so they
+/// can be committed together.
+///
+/// Each file is split by its content with the chunker, if one is given, or is read into a single
+/// block otherwise.
+/// Under a scope, every block, leaves included, is a composite of the scope, sealed with the
+/// keyring as it is made, as a sealed block is addressed by its ciphertext, which the blocks, and
+/// entries, referring to it must know.
+pub fn stage_path(
+    path: &Path,
+    scope: Option<Hash>,
+    chunker: Option<Chunker>,
+    keyring: &Keyring,
+) -> Result<Batch> {
+    let mut batch = Batch::new();
+    for (entry, blocks) in visit_path(path, scope, chunker, keyring)? {
+        for block in blocks {
+            batch.put_block(block);
+        }
+        batch.put_entry(entry);
+    }
+    Ok(batch)
+}
+
+fn visit_path(
+    path: &Path,
+    scope: Option<Hash>,
+    chunker: Option<Chunker>,
+    keyring: &Keyring,
+) -> Result<Vec<(Entry, Vec<Block>)>> {
+    if path.is_file() {
+        Ok(vec![process_file(path, scope, chunker, keyring)?])
+    } else {
+        visit_dirs(path, scope, chunker, keyring)
+    }
+}
+
+/// Reads the file into its blocks, the last of which is the one its entry refers to.
+fn process_file(
+    path: &Path,
+    scope: Option<Hash>,
+    chunker: Option<Chunker>,
+    keyring: &Keyring,
+) -> Result<(Entry, Vec<Block>)> {
     let hash = Hash::new(
         path.to_string_lossy().as_bytes(),
-        Some(HashOpts { key: scope }),
+        Some(HashOpts {
+            key: scope.to_owned(),
+        }),
     );
+    let blocks = match chunker {
+        Some(chunker) => chunk_file(path, scope, chunker, keyring)?,
+        None => vec![within(
+            read_file(path)?,
+            &scope,
+            chrono::Utc::now(),
+            keyring,
+        )?],
+    };
+    let entry = Entry::new(hash, blocks.last().expect("a file to have a root block"));
+    Ok((entry, blocks))
+}
+
+/// Wraps the bytes in a `Block::Composite` of the scope, if there is one, sealed like every other
+/// block of the scope.
+///
+/// The timestamp is sealed along with the bytes, so the leaves of a file share one, for the same
+/// bytes to be sealed, and stored, once.
+fn within(
+    bytes: Block,
+    scope: &Option<Hash>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    keyring: &Keyring,
+) -> Result<Block> {
+    match scope {
+        Some(_) => Block::Composite {
+            timestamp,
+            confidence: 1,
+            scope: scope.to_owned(),
+            data: Some(Box::new(bytes)),
+            children: None,
+            chunking: None,
+        }
+        .seal(keyring),
+        None => Ok(bytes),
+    }
+}
+
+/// Splits the file, by its content, into leaf blocks, each stored once, followed by the root
+/// referring to them, in order, which records how the file was split.
+fn chunk_file(
+    path: &Path,
+    scope: Option<Hash>,
+    chunker: Chunker,
+    keyring: &Keyring,
+) -> Result<Vec<Block>> {
+    let content = fs::read(path)?;
+    let pieces = chunker.split(&content);
+    let timestamp = chrono::Utc::now();
+    let leaves: Vec<Block> = pieces
+        .iter()
+        .map(|piece| within(Block::padded(piece), &scope, timestamp, keyring))
+        .collect::<Result<_>>()?;
+    let children = leaves
+        .iter()
+        .map(|leaf| Box::new(Block::Ref(leaf.address())))
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut blocks: Vec<Block> = leaves
+        .into_iter()
+        .filter(|leaf| seen.insert(leaf.address()))
+        .collect();
+    blocks.push(
+        Block::Composite {
+            timestamp,
+            confidence: 1,
+            scope,
+            data: None,
+            children: Some(children),
+            chunking: Some(Chunking {
+                chunker,
+                lens: pieces.iter().map(|piece| piece.len() as u32).collect(),
+            }),
+        }
+        .seal(keyring)?,
+    );
+    Ok(blocks)
+}
+
+/// Reads the file into a single `Block::Bytes`, padding its last chunk.
+fn read_file(path: &Path) -> Result<Block> {
     let mut file = File::open(path)?;
     let mut buffer = [0; BLOCK_SIZE];
     let mut chunks: Vec<[u8; BLOCK_SIZE]> = Vec::new();
@@ -87,23 +220,27 @@ fn process_file(path: &Path, scope: Option<Hash>) -> Result<Entry> {
         }
     }
 
-    let entry = Entry::new(hash, &Block::Bytes(chunks).into());
-    Ok(entry)
+    Ok(Block::Bytes(chunks))
 }
 
 // Recursive function to traverse directories and process files
-fn visit_dirs(dir: &Path, scope: Option<Hash>) -> Result<Vec<Entry>> {
-    let mut entries: Vec<Entry> = Vec::new();
+fn visit_dirs(
+    dir: &Path,
+    scope: Option<Hash>,
+    chunker: Option<Chunker>,
+    keyring: &Keyring,
+) -> Result<Vec<(Entry, Vec<Block>)>> {
+    let mut entries: Vec<(Entry, Vec<Block>)> = Vec::new();
     if dir.is_dir() {
         let read_result = fs::read_dir(dir)?;
         for entry in read_result {
             let entry = entry?;
             let path = entry.path();
             if path.is_dir() {
-                entries.append(&mut visit_dirs(&path, scope.to_owned())?);
+                entries.append(&mut visit_dirs(&path, scope.to_owned(), chunker, keyring)?);
             } else if path.is_file() {
                 let file_size = path.metadata()?.len();
-                entries.push(process_file(&path, scope.to_owned())?);
+                entries.push(process_file(&path, scope.to_owned(), chunker, keyring)?);
             }
         }
     }
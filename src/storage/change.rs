@@ -0,0 +1,97 @@
+use futures::channel::mpsc;
+use std::sync::Mutex;
+
+/// A change to the contents of a data store, carrying the affected key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// The key was written, or overwritten.
+    Written(String),
+    /// The key was removed.
+    Deleted(String),
+    /// The key was removed, to make room for another.
+    Evicted(String),
+    /// The key was copied up from a slower tier, after a read.
+    Promoted(String),
+    /// The key was forbidden, and removed.
+    Forbidden(String),
+    /// The key outlived its expiry, and was removed.
+    Expired(String),
+}
+
+impl Change {
+    pub fn key(&self) -> &str {
+        match self {
+            Change::Written(key)
+            | Change::Deleted(key)
+            | Change::Evicted(key)
+            | Change::Promoted(key)
+            | Change::Forbidden(key)
+            | Change::Expired(key) => key,
+        }
+    }
+}
+
+/// A stream of changes, as returned by `DataStore::on_change`.
+pub type Changes = mpsc::UnboundedReceiver<Change>;
+
+/// Fans changes out to every subscriber, forgetting those which have gone away.
+///
+/// Changes can be held back, e.g. while a batch is applied, and then released, or dropped, once it
+/// is known whether they stand.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<Change>>>,
+    held: Mutex<Option<Vec<Change>>>,
+}
+
+impl Notifier {
+    pub fn subscribe(&self) -> Changes {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers
+            .lock()
+            .expect("Notifier lock not to be poisoned.")
+            .push(sender);
+        receiver
+    }
+
+    pub fn notify(&self, change: Change) {
+        if let Some(held) = self
+            .held
+            .lock()
+            .expect("Notifier lock not to be poisoned.")
+            .as_mut()
+        {
+            held.push(change);
+            return;
+        }
+        self.send(change);
+    }
+
+    /// Holds back every change from now on, until `release` is called.
+    pub fn hold(&self) {
+        self.held
+            .lock()
+            .expect("Notifier lock not to be poisoned.")
+            .get_or_insert_with(Vec::new);
+    }
+
+    /// Sends the changes held back which are kept, in order, and stops holding them back.
+    pub fn release(&self, keep: impl Fn(&Change) -> bool) {
+        let held = self
+            .held
+            .lock()
+            .expect("Notifier lock not to be poisoned.")
+            .take()
+            .unwrap_or_default();
+        held.into_iter()
+            .filter(|change| keep(change))
+            .for_each(|change| self.send(change));
+    }
+
+    fn send(&self, change: Change) {
+        self.subscribers
+            .lock()
+            .expect("Notifier lock not to be poisoned.")
+            .retain(|subscriber| subscriber.unbounded_send(change.clone()).is_ok());
+    }
+}
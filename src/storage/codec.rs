@@ -0,0 +1,156 @@
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+use crate::storage::DataStoreError;
+
+/// Level used for zstd, favouring speed, since values are small, and written often.
+const ZSTD_LEVEL: i32 = 3;
+
+/// The largest value encoded, or decoded, so a corrupt, or hostile, length can't claim more
+/// memory than any value could need. It is the size of a pack segment.
+const MAX_VALUE_LEN: usize = 64 << 20;
+
+/// A compression codec, applied by a tier to every value it serializes.
+///
+/// Encoded values are prefixed with a single byte naming the codec, so that a tier can read back
+/// values written with any codec, and values which don't shrink are kept as they are.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Codec {
+    pub fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "None" => Codec::None,
+            "Lz4" => Codec::Lz4,
+            "Zstd" => Codec::Zstd,
+            _ => bail!(DataStoreError::Invalid),
+        })
+    }
+
+    pub fn to_str(&self) -> &str {
+        match self {
+            Codec::None => "None",
+            Codec::Lz4 => "Lz4",
+            Codec::Zstd => "Zstd",
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Lz4 => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self> {
+        Ok(match tag {
+            0 => Codec::None,
+            1 => Codec::Lz4,
+            2 => Codec::Zstd,
+            _ => bail!(DataStoreError::Decode),
+        })
+    }
+
+    /// Compresses the data, falling back to storing it as is, if that is no larger.
+    ///
+    /// Fails with `TooLarge` past `MAX_VALUE_LEN`, as it couldn't be decoded.
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() > MAX_VALUE_LEN {
+            bail!(DataStoreError::TooLarge);
+        }
+        let compressed = match self {
+            Codec::None => None,
+            Codec::Lz4 => Some(lz4_flex::compress_prepend_size(data)),
+            Codec::Zstd => Some(zstd::bulk::compress(data, ZSTD_LEVEL)?),
+        };
+        let (codec, payload) = match compressed {
+            Some(compressed) if compressed.len() < data.len() => (*self, compressed),
+            _ => (Codec::None, data.to_vec()),
+        };
+
+        let mut encoded = Vec::with_capacity(1 + payload.len());
+        encoded.push(codec.tag());
+        encoded.extend_from_slice(&payload);
+        Ok(encoded)
+    }
+
+    /// Decompresses data written by `encode`, with whichever codec is named in its header.
+    ///
+    /// Fails with `Decode` if it would decompress past `MAX_VALUE_LEN`, before allocating for it.
+    pub fn decode(encoded: &[u8]) -> Result<Vec<u8>> {
+        let Some((tag, payload)) = encoded.split_first() else {
+            bail!(DataStoreError::Decode);
+        };
+        Ok(match Self::from_tag(*tag)? {
+            Codec::None => payload.to_vec(),
+            Codec::Lz4 => {
+                let (len, compressed) = lz4_flex::block::uncompressed_size(payload)
+                    .map_err(|_| DataStoreError::Decode)?;
+                if len > MAX_VALUE_LEN {
+                    bail!(DataStoreError::Decode);
+                }
+                lz4_flex::decompress(compressed, len).map_err(|_| DataStoreError::Decode)?
+            }
+            Codec::Zstd => {
+                // The frame's own claim of its size isn't trusted either, so it is read up to
+                // one byte past the bound, to tell if it goes over.
+                let mut decoded = Vec::new();
+                zstd::stream::read::Decoder::new(payload)
+                    .map_err(|_| DataStoreError::Decode)?
+                    .take(MAX_VALUE_LEN as u64 + 1)
+                    .read_to_end(&mut decoded)
+                    .map_err(|_| DataStoreError::Decode)?;
+                if decoded.len() > MAX_VALUE_LEN {
+                    bail!(DataStoreError::Decode);
+                }
+                decoded
+            }
+        })
+    }
+
+    /// Decompresses data written by `encode`, as `decode` does, but returns data which was stored
+    /// uncompressed as a view of the encoded bytes, without copying it.
+    pub fn decode_bytes(encoded: Bytes) -> Result<Bytes> {
+        match encoded.first() {
+            Some(&tag) if Self::from_tag(tag)? == Codec::None => Ok(encoded.slice(1..)),
+            _ => Ok(Self::decode(&encoded)?.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_values_round_trip() {
+        let data = vec![7; 4096];
+        for codec in [Codec::None, Codec::Lz4, Codec::Zstd] {
+            let encoded = codec.encode(&data).unwrap();
+            assert_eq!(Codec::decode(&encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn lz4_size_past_the_bound_is_rejected() {
+        let mut encoded = Codec::Lz4.encode(&[7; 4096]).unwrap();
+        encoded[1..5].copy_from_slice(&(MAX_VALUE_LEN as u32 + 1).to_le_bytes());
+        assert!(Codec::decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn zstd_past_the_bound_is_rejected() {
+        let bomb = zstd::bulk::compress(&vec![0; MAX_VALUE_LEN + 1], ZSTD_LEVEL).unwrap();
+        let encoded = [&[Codec::Zstd.tag()], bomb.as_slice()].concat();
+        assert!(Codec::decode(&encoded).is_err());
+        assert!(Codec::Zstd.encode(&vec![0; MAX_VALUE_LEN + 1]).is_err());
+    }
+}
@@ -1,14 +1,15 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, io};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
+use bytes::Bytes;
 use ciborium_io::{Read, Write};
 use derive_more::{Display, From};
 use thiserror::Error;
 
 use crate::hash::Hash;
 use crate::models::Block;
-use crate::storage::DataType;
+use crate::storage::{Changes, DataType, Expiry, Filter, ListOptions};
 
 use libc;
 
@@ -18,51 +19,105 @@ pub trait DataStore<T: DataType + Send + Sync + Debug> {
     /// Returns the block associated with the key. If the key is not found, returns None
     async fn read(&self, key: &str) -> Result<T>;
 
+    /// Returns the block associated with the key, as serialized, viewing the data store's own
+    /// copy where it can, rather than copying it, e.g. from a memory-mapped file.
+    async fn read_bytes(&self, key: &str) -> Result<Bytes> {
+        Ok(self.read(key).await?.serialize().into())
+    }
+
     /// Inserts a block into the data store.
     async fn write(&mut self, key: &str, value: &T) -> Result<()>;
 
+    /// Inserts a block into the data store, until it expires, returning any blocks evicted to
+    /// make room for it, along with their expiry.
+    async fn insert(
+        &mut self,
+        key: &str,
+        value: &T,
+        expires: Option<Expiry>,
+    ) -> Result<Vec<(String, T, Option<Expiry>)>> {
+        self.write(key, value).await?;
+        self.expire(key, expires).await?;
+        Ok(Vec::new())
+    }
+
+    /// Sets when the block for the given key expires, or that it never does.
+    async fn expire(&mut self, key: &str, expires: Option<Expiry>) -> Result<()> {
+        bail!(DataStoreError::Unsupported)
+    }
+
+    /// Returns when the block for the given key expires, if it does.
+    async fn expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        bail!(DataStoreError::Unsupported)
+    }
+
+    /// Removes every expired block, returning them.
+    async fn sweep(&mut self) -> Result<Vec<(String, T)>> {
+        bail!(DataStoreError::Unsupported)
+    }
+
     /// Removes the block for the given key from the data store
     async fn delete(&mut self, key: &str) -> Result<()> {
-        todo!()
+        bail!(DataStoreError::Unsupported)
+    }
+
+    /// Returns a page of the keys in the data store, in order, selected by the given options
+    async fn list(&self, options: &ListOptions) -> Result<Vec<String>> {
+        bail!(DataStoreError::Unsupported)
+    }
+
+    /// Moves the value for the given key aside, so that it is no longer read, e.g. when corrupt
+    async fn quarantine(&mut self, key: &str) -> Result<()> {
+        self.delete(key).await
+    }
+
+    /// Moves aside whatever the data store holds which can't be decoded far enough to tell the
+    /// key it is under, returning where each was, e.g. its path
+    async fn quarantine_unreadable(&mut self) -> Result<Vec<String>> {
+        Ok(Vec::new())
     }
 
-    /// Returns a list of all the keys in the data store
-    async fn list(&self, key: Option<&str>) -> Result<Vec<&str>> {
-        todo!()
+    /// Creates a filter of the data store, for a compact, and communicable proof of membership,
+    /// in the default shape, so it merges with those of other stores
+    async fn filter(&self) -> Result<Filter> {
+        let mut filter = Filter::default();
+        self.extend_filter(&mut filter).await?;
+        Ok(filter)
     }
 
-    /// Creates a filter of the data store, for a compact, and communicable proof of membership
-    async fn filter(&self) -> Result<&str> {
-        todo!()
+    /// Inserts every key in the data store into the given filter.
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        bail!(DataStoreError::Unsupported)
     }
+
     /// Returns true if the key is in the data store.
     async fn contains(&self, key: &str) -> Result<bool> {
-        todo!()
+        bail!(DataStoreError::Unsupported)
     }
 
     /// Returns the number of blocks in the data store
     async fn len(&self) -> Result<usize> {
-        todo!()
+        bail!(DataStoreError::Unsupported)
     }
 
     /// Returns the size of the data store in bytes
     async fn size(&self) -> Result<u64> {
-        todo!()
+        bail!(DataStoreError::Unsupported)
     }
 
-    /// Register a callback function that will be called when the data store changes.
-    async fn on_change(&self, func: fn() -> Result<()>) -> Result<()> {
-        todo!()
+    /// Subscribe to a stream of the changes made to the data store.
+    async fn on_change(&self) -> Result<Changes> {
+        bail!(DataStoreError::Unsupported)
     }
 
     /// Prevent storage, and transmission of a block
     async fn forbid(&self, key: &str) -> Result<()> {
-        todo!()
+        bail!(DataStoreError::Unsupported)
     }
 
     /// Re-enable storage, and transmission of a block
     async fn allow(&self, key: &str) -> Result<()> {
-        todo!()
+        bail!(DataStoreError::Unsupported)
     }
 
     // XXX: A bit more dangeroos for now.
@@ -74,13 +129,25 @@ pub trait DataStore<T: DataType + Send + Sync + Debug> {
 
 /// The `DataStoreError` enum represents the possible errors that can occur when interacting with a data store.
 /// The error variants are mapped to the underlying POSIX error codes.
-#[derive(Debug, Display, Error)]
+#[derive(Debug, Display, Error, Clone, Copy, PartialEq, Eq)]
 pub enum DataStoreError {
     AccessDenied,
     PermissionDenied,
     NotFound,
     ConnectionRefused,
     Invalid,
+    NoSpace,
+    QuotaExceeded,
+    /// A value larger than the tier could ever hold, however much is evicted.
+    TooLarge,
+    /// Bytes which do not decode to the expected type.
+    Decode,
+    /// A value which decodes, but fails a checksum, or does not match its key.
+    Corrupt,
+    /// An I/O failure without a more specific mapping.
+    Io,
+    /// An operation the data store does not implement.
+    Unsupported,
     Unknown(i32),
 }
 
@@ -92,7 +159,70 @@ impl DataStoreError {
             libc::ECONNREFUSED => DataStoreError::ConnectionRefused,
             libc::EINVAL => DataStoreError::Invalid,
             libc::EPERM => DataStoreError::PermissionDenied,
+            libc::ENOSPC => DataStoreError::NoSpace,
+            libc::EDQUOT => DataStoreError::QuotaExceeded,
+            libc::EFBIG => DataStoreError::TooLarge,
+            libc::EILSEQ => DataStoreError::Decode,
+            libc::EBADMSG => DataStoreError::Corrupt,
+            libc::EIO => DataStoreError::Io,
+            libc::ENOTSUP => DataStoreError::Unsupported,
             _ => DataStoreError::Unknown(errno),
         }
     }
+
+    pub fn errno(&self) -> i32 {
+        match self {
+            DataStoreError::AccessDenied => libc::EACCES,
+            DataStoreError::PermissionDenied => libc::EPERM,
+            DataStoreError::NotFound => libc::ENOENT,
+            DataStoreError::ConnectionRefused => libc::ECONNREFUSED,
+            DataStoreError::Invalid => libc::EINVAL,
+            DataStoreError::NoSpace => libc::ENOSPC,
+            DataStoreError::QuotaExceeded => libc::EDQUOT,
+            DataStoreError::TooLarge => libc::EFBIG,
+            DataStoreError::Decode => libc::EILSEQ,
+            DataStoreError::Corrupt => libc::EBADMSG,
+            DataStoreError::Io => libc::EIO,
+            DataStoreError::Unsupported => libc::ENOTSUP,
+            DataStoreError::Unknown(errno) => *errno,
+        }
+    }
+
+    /// The error a failure was raised with, if any, mapping I/O errors to their nearest variant.
+    pub fn of(error: &anyhow::Error) -> Option<DataStoreError> {
+        if let Some(e) = error.downcast_ref::<DataStoreError>() {
+            return Some(*e);
+        }
+        error.downcast_ref::<io::Error>().map(DataStoreError::from)
+    }
+}
+
+impl From<&io::Error> for DataStoreError {
+    fn from(error: &io::Error) -> Self {
+        if let Some(errno) = error.raw_os_error() {
+            return DataStoreError::from_errno(errno);
+        }
+        match error.kind() {
+            io::ErrorKind::NotFound => DataStoreError::NotFound,
+            io::ErrorKind::PermissionDenied => DataStoreError::PermissionDenied,
+            io::ErrorKind::ConnectionRefused => DataStoreError::ConnectionRefused,
+            io::ErrorKind::InvalidInput => DataStoreError::Invalid,
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => DataStoreError::Decode,
+            io::ErrorKind::Unsupported => DataStoreError::Unsupported,
+            _ => DataStoreError::Io,
+        }
+    }
+}
+
+impl From<io::Error> for DataStoreError {
+    fn from(error: io::Error) -> Self {
+        DataStoreError::from(&error)
+    }
+}
+
+/// Carries the mapped errno, so a failure surfaces as the matching POSIX error.
+impl From<DataStoreError> for io::Error {
+    fn from(error: DataStoreError) -> Self {
+        io::Error::from_raw_os_error(error.errno())
+    }
 }
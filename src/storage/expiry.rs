@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// When a stored value expires, in whole seconds since the Unix epoch.
+///
+/// Tiers with a binary layout store it as a `u64`, with zero for a value which never expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Expiry(u64);
+
+impl Expiry {
+    /// Expires once the given time to live has passed, from now.
+    pub fn after(ttl: Duration) -> Self {
+        Expiry(
+            (SystemTime::now() + ttl)
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs())
+                .max(1),
+        )
+    }
+
+    pub fn from_secs(secs: u64) -> Option<Self> {
+        (secs > 0).then_some(Expiry(secs))
+    }
+
+    pub fn to_secs(expires: Option<Expiry>) -> u64 {
+        expires.map_or(0, |expires| expires.0)
+    }
+
+    pub fn is_past(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// The time left until expiry, or zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        (UNIX_EPOCH + Duration::from_secs(self.0))
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+    }
+}
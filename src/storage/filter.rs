@@ -0,0 +1,123 @@
+use anyhow::{anyhow, bail, Result};
+use bytes::BytesMut;
+use ciborium::{from_reader, into_writer};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::DataStoreError;
+
+/// The rate of false positives a filter is sized for, by default.
+pub const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// The number of keys the default filter is sized for. Every node uses the same shape, so that
+/// their filters can be merged, whatever they hold.
+pub const CAPACITY: usize = 1 << 16;
+
+/// The most hash functions a filter may use, past which a filter is refused as malformed.
+pub const MAX_HASHES: u32 = 32;
+
+/// A Bloom filter over the keys of a data store.
+///
+/// A compact, and communicable, proof of membership. A key which was inserted is always
+/// reported as present, while a key which was not is reported as absent, but for the odd
+/// false positive. Filters of the same shape can be merged, to describe the union of stores.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Shape")]
+pub struct Filter {
+    hashes: u32,
+    bits: BytesMut,
+}
+
+/// A filter as decoded, before it is checked to be usable.
+#[derive(Deserialize)]
+struct Shape {
+    hashes: u32,
+    bits: BytesMut,
+}
+
+impl TryFrom<Shape> for Filter {
+    type Error = DataStoreError;
+
+    /// Fails with `Invalid` for a filter without bits, or with more hashes than `MAX_HASHES`.
+    fn try_from(Shape { hashes, bits }: Shape) -> Result<Self, Self::Error> {
+        if bits.is_empty() || !(1..=MAX_HASHES).contains(&hashes) {
+            return Err(DataStoreError::Invalid);
+        }
+        Ok(Filter { hashes, bits })
+    }
+}
+
+impl Filter {
+    /// Creates a filter sized for `capacity` keys, at the default false positive rate.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_rate(capacity, FALSE_POSITIVE_RATE)
+    }
+
+    pub fn with_rate(capacity: usize, rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let rate = rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let len = (-capacity * rate.ln() / std::f64::consts::LN_2.powi(2)).ceil() as usize;
+        let len = len.max(8).div_ceil(8);
+        let hashes = ((len * 8) as f64 / capacity * std::f64::consts::LN_2).round() as u32;
+        Filter {
+            hashes: hashes.clamp(1, MAX_HASHES),
+            bits: BytesMut::zeroed(len),
+        }
+    }
+}
+
+impl Default for Filter {
+    /// The shape every node uses, sized for `CAPACITY` keys.
+    fn default() -> Self {
+        Self::new(CAPACITY)
+    }
+}
+
+impl Filter {
+    pub fn insert(&mut self, key: &str) {
+        for index in self.indices(key) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// Returns true if the key is likely held, false if it is certainly not.
+    pub fn contains(&self, key: &str) -> bool {
+        self.indices(key)
+            .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Folds another filter, of the same shape, into this one.
+    pub fn merge(&mut self, other: &Filter) -> Result<()> {
+        if self.hashes != other.hashes || self.bits.len() != other.bits.len() {
+            bail!(DataStoreError::Invalid);
+        }
+        self.bits
+            .iter_mut()
+            .zip(other.bits.iter())
+            .for_each(|(bits, other)| *bits |= other);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|bits| *bits == 0)
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        into_writer(self, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        from_reader(bytes).map_err(|e| anyhow!("{e}"))
+    }
+
+    /// Derives the bit indices of a key, by double hashing its blake3 hash.
+    fn indices(&self, key: &str) -> impl Iterator<Item = usize> {
+        let hash = blake3::hash(key.as_bytes());
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().expect("slice is 8 bytes"));
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("slice is 8 bytes"));
+        let len = (self.bits.len() * 8) as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{bail, Result};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use hashbrown::HashMap;
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+use tracing::debug;
+
+use crate::{
+    hash::Hash,
+    storage::{DataStoreError, KeySet},
+};
+
+/// Context for deriving the encryption key of a scope, from its secret.
+const KEY_CONTEXT: &str = "gra 2024-06 scope encryption key";
+
+/// Context for deriving the key nonces are hashed with, from the encryption key of a scope.
+const NONCE_CONTEXT: &str = "gra 2024-10 scope nonce key";
+
+pub const NONCE_LEN: usize = 24;
+
+/// The secrets of the scopes held by this node, loaded from a keyfile, which new scopes are
+/// appended to.
+///
+/// A scope is named by the hash of its secret, which is public, e.g. within entry keys, while
+/// the key its blocks are encrypted with is derived from the secret itself.
+#[derive(Debug, Default)]
+pub struct Keyring {
+    keys: RwLock<HashMap<String, [u8; 32]>>,
+}
+
+impl Keyring {
+    /// Loads the secrets in the keyfile, one per line, with blank lines, and `#` comments,
+    /// ignored, so any process given the file can read the scopes it holds. A missing file holds
+    /// none.
+    pub fn load(path: &Path) -> Result<Self> {
+        let keyring = Keyring::default();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for secret in contents
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+        {
+            keyring.insert(secret);
+        }
+        debug!("Loaded {} scope secrets from {path:?}", keyring.len());
+        Ok(keyring)
+    }
+
+    /// The keyfile used when none is given, `$HOME/.gra/keys`.
+    pub fn default_path() -> PathBuf {
+        KeySet::default_path("keys")
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys
+            .read()
+            .expect("Keyring lock not to be poisoned.")
+            .len()
+    }
+
+    /// Adds the secret of a scope, returning the hash the scope is named by.
+    pub fn insert(&self, secret: &str) -> Hash {
+        let scope = Hash::new(secret.as_bytes(), None);
+        self.keys
+            .write()
+            .expect("Keyring lock not to be poisoned.")
+            .insert(
+                scope.to_hex(),
+                blake3::derive_key(KEY_CONTEXT, secret.as_bytes()),
+            );
+        scope
+    }
+
+    /// Adds the secret of a scope, appending it to the keyfile, created readable by its owner
+    /// alone, unless already held, so later processes given the file hold it too.
+    ///
+    /// Fails with `Invalid` if the secret wouldn't read back as it is, i.e. is empty, spans lines,
+    /// holds a `#`, or starts, or ends, with whitespace.
+    pub fn add(&self, path: &Path, secret: &str) -> Result<Hash> {
+        if secret.is_empty() || secret.trim() != secret || secret.contains(['\n', '#']) {
+            bail!(DataStoreError::Invalid);
+        }
+        if !self.contains(&Hash::new(secret.as_bytes(), None)) {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o600)
+                .open(path)?;
+            writeln!(file, "{secret}")?;
+            file.sync_all()?;
+        }
+        Ok(self.insert(secret))
+    }
+
+    pub fn contains(&self, scope: &Hash) -> bool {
+        self.keys
+            .read()
+            .expect("Keyring lock not to be poisoned.")
+            .contains_key(&scope.to_hex())
+    }
+
+    fn key(&self, scope: &Hash) -> Result<[u8; 32]> {
+        let keys = self.keys.read().expect("Keyring lock not to be poisoned.");
+        let Some(key) = keys.get(&scope.to_hex()) else {
+            bail!(DataStoreError::PermissionDenied);
+        };
+        Ok(*key)
+    }
+
+    /// Encrypts the data under the scope, binding it to `aad`, returning the nonce and ciphertext.
+    ///
+    /// The nonce is derived from both, so the same data is always sealed the same, and so has a
+    /// single address, within a scope, while different data never shares a nonce.
+    pub fn seal(
+        &self,
+        scope: &Hash,
+        aad: &[u8],
+        data: &[u8],
+    ) -> Result<([u8; NONCE_LEN], Vec<u8>)> {
+        let key = self.key(scope)?;
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(
+            &blake3::Hasher::new_keyed(&blake3::derive_key(NONCE_CONTEXT, &key))
+                .update(&(aad.len() as u64).to_le_bytes())
+                .update(aad)
+                .update(data)
+                .finalize()
+                .as_bytes()[..NONCE_LEN],
+        );
+        let ciphertext = XChaCha20Poly1305::new(&key.into())
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: data, aad })
+            .map_err(|_| DataStoreError::Invalid)?;
+        Ok((nonce, ciphertext))
+    }
+
+    /// Decrypts, and authenticates, data sealed under the scope with the same `aad`.
+    pub fn open(
+        &self,
+        scope: &Hash,
+        aad: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        if nonce.len() != NONCE_LEN {
+            bail!(DataStoreError::Invalid);
+        }
+        let data = XChaCha20Poly1305::new(&self.key(scope)?.into())
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| DataStoreError::Corrupt)?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_secrets_are_loaded_back() {
+        let dir = std::env::temp_dir().join(format!("gra-keyring-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("keys");
+        let keyring = Keyring::default();
+        let scope = keyring.add(&path, "secret").unwrap();
+        assert_eq!(keyring.add(&path, "secret").unwrap(), scope);
+        assert!(keyring.add(&path, "# secret").is_err());
+        assert!(keyring.add(&path, "secret\n").is_err());
+
+        let loaded = Keyring::load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded.contains(&scope));
+    }
+}
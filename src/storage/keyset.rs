@@ -0,0 +1,158 @@
+use anyhow::Result;
+use hashbrown::HashSet;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+use tracing::debug;
+
+/// A persisted set of keys, such as those forbidden, or pinned.
+///
+/// The file format is one hex key per line, with blank lines and `#` comments ignored, so a
+/// list can be exported from one node and imported into the others.
+#[derive(Debug, Default)]
+pub struct KeySet {
+    path: Option<PathBuf>,
+    keys: RwLock<HashSet<String>>,
+}
+
+impl KeySet {
+    /// Opens the set at the given path, which is created on the first change.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let keys = match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents).collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        debug!("Loaded {} keys from {path:?}", keys.len());
+        Ok(KeySet {
+            path: Some(path),
+            keys: RwLock::new(keys),
+        })
+    }
+
+    /// The location of the named set, when none is given, e.g. `$HOME/.gra/denylist`.
+    pub fn default_path(name: &str) -> PathBuf {
+        std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join(".gra")
+            .join(name)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys
+            .read()
+            .expect("KeySet lock not to be poisoned.")
+            .contains(&Self::normalise(key))
+    }
+
+    /// Adds the key, returning false if it was already present.
+    pub fn insert(&self, key: &str) -> Result<bool> {
+        let inserted = self
+            .keys
+            .write()
+            .expect("KeySet lock not to be poisoned.")
+            .insert(Self::normalise(key));
+        if inserted {
+            self.save()?;
+        }
+        Ok(inserted)
+    }
+
+    /// Removes the key, returning false if it was not present.
+    pub fn remove(&self, key: &str) -> Result<bool> {
+        let removed = self
+            .keys
+            .write()
+            .expect("KeySet lock not to be poisoned.")
+            .remove(&Self::normalise(key));
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Adds every key in the given file, returning how many were newly added.
+    pub fn import(&self, path: &Path) -> Result<usize> {
+        let contents = fs::read_to_string(path)?;
+        let imported = {
+            let mut keys = self.keys.write().expect("KeySet lock not to be poisoned.");
+            Self::parse(&contents)
+                .filter(|key| keys.insert(key.to_owned()))
+                .count()
+        };
+        if imported > 0 {
+            self.save()?;
+        }
+        Ok(imported)
+    }
+
+    pub fn export(&self, path: &Path) -> Result<()> {
+        Self::write(path, &self.to_string())
+    }
+
+    /// Returns a copy of every key in the set.
+    pub fn keys(&self) -> Vec<String> {
+        self.keys
+            .read()
+            .expect("KeySet lock not to be poisoned.")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys
+            .read()
+            .expect("KeySet lock not to be poisoned.")
+            .len()
+    }
+
+    fn parse(contents: &str) -> impl Iterator<Item = String> + '_ {
+        contents
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(Self::normalise)
+    }
+
+    fn normalise(key: &str) -> String {
+        key.trim().to_ascii_lowercase()
+    }
+
+    fn save(&self) -> Result<()> {
+        match &self.path {
+            Some(path) => Self::write(path, &self.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes to a sibling file, and renames it into place, so a crash never loses the list.
+    fn write(path: &Path, contents: &str) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let staging_path = path.with_extension(format!("{:016x}", rand::random::<u64>()));
+        fs::write(&staging_path, contents)?;
+        fs::rename(&staging_path, path)?;
+        Ok(())
+    }
+}
+
+/// The keys which are neither stored, served, nor fetched.
+pub type Denylist = KeySet;
+
+/// The entry keys which, along with every block they reach, are kept by garbage collection.
+pub type Pins = KeySet;
+
+impl std::fmt::Display for KeySet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys = self.keys.read().expect("KeySet lock not to be poisoned.");
+        let mut keys: Vec<&String> = keys.iter().collect();
+        keys.sort();
+        keys.iter().try_for_each(|key| writeln!(f, "{key}"))
+    }
+}
@@ -0,0 +1,90 @@
+use crate::hash::Hash;
+
+/// Selects, and pages through, the keys returned by `DataStore::list`.
+///
+/// Keys are returned in order, so the last key of one page is the cursor for the next.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ListOptions {
+    pub prefix: Option<String>,
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl ListOptions {
+    /// Lists the keys starting with the given prefix.
+    pub fn prefix(prefix: &str) -> Self {
+        Self {
+            prefix: Some(prefix.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    /// Lists the keys within the given scope, i.e. those formatted as `scope|hash`, where the scope
+    /// is written out in full, with the scopes it is nested in.
+    pub fn scope(scope: &Hash) -> Self {
+        Self::prefix(&format!("{scope}|"))
+    }
+
+    /// Continues a listing, from after the given key.
+    pub fn after(mut self, key: &str) -> Self {
+        self.after = Some(key.to_owned());
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.prefix
+            .as_deref()
+            .map_or(true, |prefix| key.starts_with(prefix))
+            && self.after.as_deref().map_or(true, |after| key > after)
+    }
+
+    /// Filters, orders, and truncates the keys to a single page.
+    pub fn page(&self, keys: impl IntoIterator<Item = String>) -> Vec<String> {
+        let mut keys: Vec<String> = keys.into_iter().filter(|key| self.matches(key)).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        if let Some(limit) = self.limit {
+            keys.truncate(limit);
+        }
+        keys
+    }
+}
+
+/// A listed key, along with the positions of the tiers holding it, fastest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listed {
+    pub key: String,
+    pub tiers: Vec<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::HashOpts;
+
+    fn scoped(data: &[u8], scope: &Hash) -> Hash {
+        Hash::new(
+            data,
+            Some(HashOpts {
+                key: Some(scope.to_owned()),
+            }),
+        )
+    }
+
+    #[test]
+    fn scope_matches_keys_within_a_nested_scope() {
+        let outer = Hash::new(b"outer", None);
+        let inner = scoped(b"inner", &outer);
+        let options = ListOptions::scope(&inner);
+
+        assert!(options.matches(&scoped(b"within", &inner).to_string()));
+        assert!(!options.matches(&scoped(b"beside", &outer).to_string()));
+        assert!(!options.matches(&scoped(b"elsewhere", &scoped(b"other", &outer)).to_string()));
+        assert!(!options.matches(&Hash::new(b"unscoped", None).to_string()));
+    }
+}
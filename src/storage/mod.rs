@@ -1,39 +1,116 @@
 use async_trait::async_trait;
-use std::{any, fmt::Debug};
+use std::{any, fmt::Debug, time::Duration};
 
 use anyhow::Result;
+use bytes::Bytes;
 use ciborium_io::{Read, Write};
 use libp2p::{kad::store::MemoryStore, relay::client::new};
 
-use crate::models::{Block, Entry};
+use crate::{
+    hash::Hash,
+    models::{Block, Entry},
+};
 
 mod datastore;
 pub use datastore::{DataStore, DataStoreError};
 
+mod codec;
+pub use codec::Codec;
+
+mod change;
+pub use change::{Change, Changes, Notifier};
+
+mod keyring;
+pub use keyring::Keyring;
+
+mod keyset;
+pub use keyset::{Denylist, KeySet, Pins};
+
+mod expiry;
+pub use expiry::Expiry;
+
+mod filter;
+pub use filter::Filter;
+
+mod quota;
+pub use quota::{Quota, Quotas, Usage};
+
+mod listing;
+pub use listing::{ListOptions, Listed};
+
 mod tier;
-pub use tier::{MemoryStorage, Tier};
+pub use tier::{
+    DiskStorage, Eviction, PackStorage, ProcessStorage, RemoteStorage, ShmStorage, Tier, TierConfig,
+};
 
 pub trait DataKey {
     fn key(&self) -> String;
 }
 
 pub trait DataType: DataKey + Send + Sync + Clone + Debug {
+    /// Name used to keep each type apart, when tiers are shared, e.g. the directory on disk.
+    const NAMESPACE: &'static str;
+
     fn serialize(&self) -> Vec<u8>;
-    fn deserialize(data: &[u8]) -> Self
+
+    /// Decodes a serialized value, failing with `Decode` if the data isn't one.
+    fn deserialize(data: &[u8]) -> Result<Self>
     where
         Self: Sized;
+
+    /// How long the value is kept for, once written, if not forever.
+    fn ttl(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The scope the value is accounted to, if any.
+    fn scope(&self) -> Option<Hash> {
+        None
+    }
+
+    /// Encrypts the value, if it belongs to a scope, so that tiers only ever hold ciphertext.
+    fn seal(&self, _keyring: &Keyring) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(self.clone())
+    }
+
+    /// Decrypts a sealed value, if the keyring holds its scope, or returns it as it is.
+    fn open(&self, _keyring: &Keyring) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(self.clone())
+    }
+
+    /// Whether the value still hashes to the key it is stored under, recomputed from what it holds.
+    fn is_intact(&self, key: &str, _keyring: &Keyring) -> bool {
+        self.key() == key
+    }
+
+    /// Whether the serialized value is sealed, and so must be opened before it is read, judged
+    /// without decoding it.
+    fn is_sealed(_data: &[u8]) -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
 }
 
 #[derive(Debug)]
 pub enum Storage<T: DataType> {
-    // The Process should also be a storage tier, represented by a HashMap
-    // Process(ProcessStorage),
-    // This should become a file in /dev/shm
-    Memory(MemoryStorage<T>),
+    /// Held within the current process.
+    Process(ProcessStorage<T>),
+    /// Shared between processes on the same host, through a file in /dev/shm.
+    Shm(ShmStorage<T>),
     // Does a file in /tmp make any sense?
-    // Disk(DiskStorage),
-    // Disk(DiskStorage),
-    // Remote(RemoteStorage),
+    Disk(DiskStorage<T>),
+    /// Persisted as append-only segment files, suited to many small values.
+    Pack(PackStorage<T>),
+    /// The network, reached through the node.
+    Remote(RemoteStorage<T>),
 }
 
 #[async_trait]
@@ -43,33 +120,166 @@ where
 {
     async fn read(&self, key: &str) -> Result<T> {
         match self {
-            Storage::Memory(storage) => storage.read(key).await,
-            // Storage::Disk(storage) => storage.read(key).await,
-            // Storage::Remote(storage) => storage.read(key).await,
+            Storage::Process(storage) => storage.read(key).await,
+            Storage::Shm(storage) => storage.read(key).await,
+            Storage::Disk(storage) => storage.read(key).await,
+            Storage::Pack(storage) => storage.read(key).await,
+            Storage::Remote(storage) => storage.read(key).await,
+        }
+    }
+
+    async fn read_bytes(&self, key: &str) -> Result<Bytes> {
+        match self {
+            Storage::Process(storage) => storage.read_bytes(key).await,
+            Storage::Shm(storage) => storage.read_bytes(key).await,
+            Storage::Disk(storage) => storage.read_bytes(key).await,
+            Storage::Pack(storage) => storage.read_bytes(key).await,
+            Storage::Remote(storage) => storage.read_bytes(key).await,
         }
     }
 
     async fn write(&mut self, key: &str, value: &T) -> Result<()> {
         match self {
-            Storage::Memory(storage) => storage.write(key, value).await,
-            // Storage::Disk(storage) => storage.insert(key, value),
-            // Storage::Remote(storage) => storage.insert(key, value),
+            Storage::Process(storage) => storage.write(key, value).await,
+            Storage::Shm(storage) => storage.write(key, value).await,
+            Storage::Disk(storage) => storage.write(key, value).await,
+            Storage::Pack(storage) => storage.write(key, value).await,
+            Storage::Remote(storage) => storage.write(key, value).await,
+        }
+    }
+
+    async fn insert(
+        &mut self,
+        key: &str,
+        value: &T,
+        expires: Option<Expiry>,
+    ) -> Result<Vec<(String, T, Option<Expiry>)>> {
+        match self {
+            Storage::Process(storage) => storage.insert(key, value, expires).await,
+            Storage::Shm(storage) => storage.insert(key, value, expires).await,
+            Storage::Disk(storage) => storage.insert(key, value, expires).await,
+            Storage::Pack(storage) => storage.insert(key, value, expires).await,
+            Storage::Remote(storage) => storage.insert(key, value, expires).await,
+        }
+    }
+
+    async fn expire(&mut self, key: &str, expires: Option<Expiry>) -> Result<()> {
+        match self {
+            Storage::Process(storage) => storage.expire(key, expires).await,
+            Storage::Shm(storage) => storage.expire(key, expires).await,
+            Storage::Disk(storage) => storage.expire(key, expires).await,
+            Storage::Pack(storage) => storage.expire(key, expires).await,
+            Storage::Remote(storage) => storage.expire(key, expires).await,
+        }
+    }
+
+    async fn expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        match self {
+            Storage::Process(storage) => storage.expiry(key).await,
+            Storage::Shm(storage) => storage.expiry(key).await,
+            Storage::Disk(storage) => storage.expiry(key).await,
+            Storage::Pack(storage) => storage.expiry(key).await,
+            Storage::Remote(storage) => storage.expiry(key).await,
+        }
+    }
+
+    async fn sweep(&mut self) -> Result<Vec<(String, T)>> {
+        match self {
+            Storage::Process(storage) => storage.sweep().await,
+            Storage::Shm(storage) => storage.sweep().await,
+            Storage::Disk(storage) => storage.sweep().await,
+            Storage::Pack(storage) => storage.sweep().await,
+            Storage::Remote(storage) => storage.sweep().await,
         }
     }
 
     async fn delete(&mut self, key: &str) -> Result<()> {
         match self {
-            Storage::Memory(storage) => storage.delete(key).await,
-            // Storage::Disk(storage) => storage.delete(key),
-            // Storage::Remote(storage) => storage.delete(key),
+            Storage::Process(storage) => storage.delete(key).await,
+            Storage::Shm(storage) => storage.delete(key).await,
+            Storage::Disk(storage) => storage.delete(key).await,
+            Storage::Pack(storage) => storage.delete(key).await,
+            Storage::Remote(storage) => storage.delete(key).await,
+        }
+    }
+
+    async fn list(&self, options: &ListOptions) -> Result<Vec<String>> {
+        match self {
+            Storage::Process(storage) => storage.list(options).await,
+            Storage::Shm(storage) => storage.list(options).await,
+            Storage::Disk(storage) => storage.list(options).await,
+            Storage::Pack(storage) => storage.list(options).await,
+            Storage::Remote(storage) => storage.list(options).await,
+        }
+    }
+
+    async fn contains(&self, key: &str) -> Result<bool> {
+        match self {
+            Storage::Process(storage) => storage.contains(key).await,
+            Storage::Shm(storage) => storage.contains(key).await,
+            Storage::Disk(storage) => storage.contains(key).await,
+            Storage::Pack(storage) => storage.contains(key).await,
+            Storage::Remote(storage) => storage.contains(key).await,
+        }
+    }
+
+    async fn len(&self) -> Result<usize> {
+        match self {
+            Storage::Process(storage) => storage.len().await,
+            Storage::Shm(storage) => storage.len().await,
+            Storage::Disk(storage) => storage.len().await,
+            Storage::Pack(storage) => storage.len().await,
+            Storage::Remote(storage) => storage.len().await,
+        }
+    }
+
+    async fn size(&self) -> Result<u64> {
+        match self {
+            Storage::Process(storage) => storage.size().await,
+            Storage::Shm(storage) => storage.size().await,
+            Storage::Disk(storage) => storage.size().await,
+            Storage::Pack(storage) => storage.size().await,
+            Storage::Remote(storage) => storage.size().await,
+        }
+    }
+
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        match self {
+            Storage::Process(storage) => storage.extend_filter(filter).await,
+            Storage::Shm(storage) => storage.extend_filter(filter).await,
+            Storage::Disk(storage) => storage.extend_filter(filter).await,
+            Storage::Pack(storage) => storage.extend_filter(filter).await,
+            Storage::Remote(storage) => storage.extend_filter(filter).await,
+        }
+    }
+
+    async fn on_change(&self) -> Result<Changes> {
+        match self {
+            Storage::Process(storage) => storage.on_change().await,
+            Storage::Shm(storage) => storage.on_change().await,
+            Storage::Disk(storage) => storage.on_change().await,
+            Storage::Pack(storage) => storage.on_change().await,
+            Storage::Remote(storage) => storage.on_change().await,
+        }
+    }
+
+    async fn quarantine(&mut self, key: &str) -> Result<()> {
+        match self {
+            Storage::Process(storage) => storage.quarantine(key).await,
+            Storage::Shm(storage) => storage.quarantine(key).await,
+            Storage::Disk(storage) => storage.quarantine(key).await,
+            Storage::Pack(storage) => storage.quarantine(key).await,
+            Storage::Remote(storage) => storage.quarantine(key).await,
         }
     }
 
-    async fn list(&self, key: Option<&str>) -> Result<Vec<&str>> {
+    async fn quarantine_unreadable(&mut self) -> Result<Vec<String>> {
         match self {
-            Storage::Memory(storage) => storage.list(key).await,
-            // Storage::Disk(storage) => storage.list(),
-            // Storage::Remote(storage) => storage.list(),
+            Storage::Process(storage) => storage.quarantine_unreadable().await,
+            Storage::Shm(storage) => storage.quarantine_unreadable().await,
+            Storage::Disk(storage) => storage.quarantine_unreadable().await,
+            Storage::Pack(storage) => storage.quarantine_unreadable().await,
+            Storage::Remote(storage) => storage.quarantine_unreadable().await,
         }
     }
 }
@@ -0,0 +1,130 @@
+use anyhow::{bail, Result};
+use hashbrown::HashMap;
+use std::sync::RwLock;
+
+use crate::{hash::Hash, storage::DataStoreError};
+
+/// The objects, and bytes, held by a tier, or a scope.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// The most a scope may hold, with either limit left unset for no limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub objects: Option<u64>,
+    pub bytes: Option<u64>,
+}
+
+impl Quota {
+    /// Parses a quota as `BYTES`, or `BYTES,OBJECTS`.
+    pub fn from_str(s: &str) -> Result<Self> {
+        let (bytes, objects) = match s.split_once(',') {
+            Some((bytes, objects)) => (bytes, Some(objects)),
+            None => (s, None),
+        };
+        Ok(Quota {
+            bytes: Some(bytes.trim().parse().map_err(|_| DataStoreError::Invalid)?),
+            objects: objects
+                .map(|objects| objects.trim().parse())
+                .transpose()
+                .map_err(|_| DataStoreError::Invalid)?,
+        })
+    }
+}
+
+/// The usage of every scope, against the quotas set on them.
+///
+/// Values outside of any scope are accounted under the empty scope, which has no quota.
+#[derive(Debug, Default)]
+pub struct Quotas {
+    limits: RwLock<HashMap<String, Quota>>,
+    usage: RwLock<HashMap<String, Usage>>,
+}
+
+impl Quotas {
+    /// The name a scope is accounted under.
+    pub fn name(scope: Option<&Hash>) -> String {
+        scope.map(Hash::to_hex).unwrap_or_default()
+    }
+
+    pub fn set(&self, scope: &Hash, quota: Quota) {
+        self.limits
+            .write()
+            .expect("Quotas lock not to be poisoned.")
+            .insert(Self::name(Some(scope)), quota);
+    }
+
+    pub fn quota(&self, scope: Option<&Hash>) -> Quota {
+        self.limits
+            .read()
+            .expect("Quotas lock not to be poisoned.")
+            .get(&Self::name(scope))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn usage(&self, scope: Option<&Hash>) -> Usage {
+        self.usage
+            .read()
+            .expect("Quotas lock not to be poisoned.")
+            .get(&Self::name(scope))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The usage of every scope holding anything, ordered by name.
+    pub fn usages(&self) -> Vec<(String, Usage)> {
+        let mut usages: Vec<(String, Usage)> = self
+            .usage
+            .read()
+            .expect("Quotas lock not to be poisoned.")
+            .iter()
+            .filter(|(_, usage)| usage.objects > 0)
+            .map(|(name, usage)| (name.to_owned(), *usage))
+            .collect();
+        usages.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        usages
+    }
+
+    /// Grows the scope by the given amounts, failing with `QuotaExceeded`, and recording nothing,
+    /// if that would exceed its quota. Shrinking a scope is always allowed, even one already over
+    /// its quota.
+    ///
+    /// The usage is checked, and recorded, under one lock, so concurrent writers can't together
+    /// exceed the quota. A reservation for a write which then fails is undone with `record`.
+    pub fn reserve(&self, scope: Option<&Hash>, objects: i64, bytes: i64) -> Result<()> {
+        let quota = self.quota(scope);
+        let mut usage = self.usage.write().expect("Quotas lock not to be poisoned.");
+        let usage = usage.entry(Self::name(scope)).or_default();
+        let exceeds = |limit: Option<u64>, used: u64, delta: i64| {
+            delta > 0 && limit.is_some_and(|limit| used.saturating_add_signed(delta) > limit)
+        };
+        if exceeds(quota.objects, usage.objects, objects)
+            || exceeds(quota.bytes, usage.bytes, bytes)
+        {
+            bail!(DataStoreError::QuotaExceeded);
+        }
+        usage.objects = usage.objects.saturating_add_signed(objects);
+        usage.bytes = usage.bytes.saturating_add_signed(bytes);
+        Ok(())
+    }
+
+    /// Adjusts the usage of the scope by the given amounts.
+    pub fn record(&self, scope: Option<&Hash>, objects: i64, bytes: i64) {
+        let mut usage = self.usage.write().expect("Quotas lock not to be poisoned.");
+        let usage = usage.entry(Self::name(scope)).or_default();
+        usage.objects = usage.objects.saturating_add_signed(objects);
+        usage.bytes = usage.bytes.saturating_add_signed(bytes);
+    }
+
+    /// Forgets all usage, ahead of it being counted again.
+    pub fn reset(&self) {
+        self.usage
+            .write()
+            .expect("Quotas lock not to be poisoned.")
+            .clear();
+    }
+}
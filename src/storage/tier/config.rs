@@ -0,0 +1,140 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+
+use super::Tier;
+use crate::{
+    node::Client,
+    storage::{Codec, DataStoreError},
+};
+
+/// How a tier makes room, once it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    /// Evict the least recently used values.
+    Lru,
+    /// Evict the oldest values, regardless of use.
+    Fifo,
+    /// Evict nothing, refusing the write instead, so it falls through to the tier below.
+    Reject,
+}
+
+impl Eviction {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Lru" => Ok(Eviction::Lru),
+            "Fifo" => Ok(Eviction::Fifo),
+            "Reject" => Ok(Eviction::Reject),
+            _ => bail!(DataStoreError::Invalid),
+        }
+    }
+
+    pub fn to_str(&self) -> &str {
+        match self {
+            Eviction::Lru => "Lru",
+            Eviction::Fifo => "Fifo",
+            Eviction::Reject => "Reject",
+        }
+    }
+}
+
+/// A named tier, and the policies it is opened with. Unset policies take the tier's defaults.
+#[derive(Debug, Clone)]
+pub struct TierConfig {
+    pub name: String,
+    pub tier: Tier,
+    pub max_size: Option<usize>,
+    pub codec: Option<Codec>,
+    pub eviction: Option<Eviction>,
+}
+
+impl From<Tier> for TierConfig {
+    fn from(tier: Tier) -> Self {
+        TierConfig {
+            name: tier.to_str().to_lowercase(),
+            tier,
+            max_size: None,
+            codec: None,
+            eviction: None,
+        }
+    }
+}
+
+/// A tier as written in the config file, e.g.
+///
+/// ```toml
+/// [[tier]]
+/// name = "hot"
+/// kind = "Process"
+/// max_size = 65536
+/// eviction = "Fifo"
+///
+/// [[tier]]
+/// name = "cold"
+/// kind = "Disk"
+/// path = "/var/lib/gra"
+/// codec = "Zstd"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TierEntry {
+    name: String,
+    kind: String,
+    path: Option<PathBuf>,
+    max_size: Option<usize>,
+    codec: Option<String>,
+    eviction: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TierFile {
+    #[serde(default)]
+    tier: Vec<TierEntry>,
+}
+
+impl TierConfig {
+    /// The config file used when none is given, `$HOME/.gra/tiers.toml`.
+    pub fn default_path() -> PathBuf {
+        Tier::default_disk_root().join("tiers.toml")
+    }
+
+    /// Reads the ordered list of tiers from the config file, from the fastest to the slowest.
+    ///
+    /// A `Remote` tier is reached through the given client, and is refused without one.
+    pub fn load(path: &PathBuf, client: Option<&Client>) -> Result<Vec<TierConfig>> {
+        Self::parse(&fs::read_to_string(path)?, client)
+    }
+
+    pub fn parse(s: &str, client: Option<&Client>) -> Result<Vec<TierConfig>> {
+        let file: TierFile = toml::from_str(s).map_err(|e| anyhow!("{e}"))?;
+        if file.tier.is_empty() {
+            bail!(DataStoreError::Invalid);
+        }
+        let mut configs: Vec<TierConfig> = Vec::with_capacity(file.tier.len());
+        for entry in file.tier {
+            if configs.iter().any(|config| config.name == entry.name) {
+                bail!(DataStoreError::Invalid);
+            }
+            let tier = match (entry.kind.as_str(), entry.path) {
+                ("Disk", Some(path)) => Tier::Disk(path),
+                ("Pack", Some(path)) => Tier::Pack(path),
+                ("Remote", None) => Tier::Remote(client.cloned().ok_or(DataStoreError::Invalid)?),
+                (kind, None) => Tier::from_str(kind)?,
+                (_, Some(_)) => bail!(DataStoreError::Invalid),
+            };
+            configs.push(TierConfig {
+                name: entry.name,
+                tier,
+                max_size: entry.max_size,
+                codec: entry.codec.as_deref().map(Codec::from_str).transpose()?,
+                eviction: entry
+                    .eviction
+                    .as_deref()
+                    .map(Eviction::from_str)
+                    .transpose()?,
+            });
+        }
+        Ok(configs)
+    }
+}
@@ -1,60 +1,502 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use async_std::path::PathBuf;
 use async_trait::async_trait;
+use bytes::Bytes;
 use ciborium::{from_reader, into_writer};
 use ciborium_io::{Read, Write};
-use hashbrown::HashMap;
-use std::fs::{File, OpenOptions};
-use tracing::debug;
+use futures::AsyncWriteExt;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, marker::PhantomData, ops::Range};
+use tracing::{debug, warn};
 
-use super::{DataStore, DataType};
+use crate::storage::{
+    Change, Changes, Codec, DataStoreError, DataType, Expiry, Filter, ListOptions, Notifier,
+};
+
+use super::DataStore;
+
+/// Directory, beneath the root, used to stage writes before they are renamed into place.
+const STAGING_DIR: &str = "tmp";
+
+/// Directory, beneath the root, holding corrupt files moved aside by a scrub.
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// Number of hex characters, of the key hash, used for each level of the fan-out.
+const SHARD_WIDTH: usize = 2;
+const SHARD_DEPTH: usize = 2;
+
+/// The on-disk representation of a value, keeping the key so that it can be listed, and the data
+/// encoded by the tier's codec.
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    key: String,
+    data: Bytes,
+    #[serde(default)]
+    expires: Option<Expiry>,
+}
+
+impl Envelope {
+    fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires.is_past())
+    }
+}
+
+/// Where the fields of an encoded `Envelope` lie, found by walking its CBOR, without decoding, or
+/// copying, the data.
+#[derive(Debug)]
+struct Layout {
+    key: Range<usize>,
+    data: Range<usize>,
+    expires: Option<Expiry>,
+}
+
+/// CBOR major types, and the simple value for null, as written for an `Envelope`.
+const UINT: u8 = 0;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const MAP: u8 = 5;
+const SIMPLE: u8 = 7;
+const NULL: u64 = 22;
+
+impl Layout {
+    /// Parses the layout of an envelope, or None if the bytes are not one.
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let mut cursor = 0;
+        let (MAP, fields) = Self::head(bytes, &mut cursor)? else {
+            return None;
+        };
+        let (mut key, mut data, mut expires) = (None, None, None);
+        for _ in 0..fields {
+            let name = Self::string(bytes, &mut cursor, TEXT)?;
+            match &bytes[name] {
+                b"key" => key = Some(Self::string(bytes, &mut cursor, TEXT)?),
+                b"data" => data = Some(Self::string(bytes, &mut cursor, BYTES)?),
+                b"expires" => {
+                    expires = match Self::head(bytes, &mut cursor)? {
+                        (UINT, secs) => Expiry::from_secs(secs),
+                        (SIMPLE, NULL) => None,
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(Layout {
+            key: key?,
+            data: data?,
+            expires,
+        })
+    }
+
+    /// Reads the head of a CBOR item, returning its major type, and argument.
+    fn head(bytes: &[u8], cursor: &mut usize) -> Option<(u8, u64)> {
+        let initial = *bytes.get(*cursor)?;
+        *cursor += 1;
+        let len = match initial & 0x1f {
+            info @ 0..=23 => return Some((initial >> 5, info as u64)),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return None,
+        };
+        let argument = bytes
+            .get(*cursor..*cursor + len)?
+            .iter()
+            .fold(0, |argument, byte| argument << 8 | *byte as u64);
+        *cursor += len;
+        Some((initial >> 5, argument))
+    }
+
+    /// Reads a text, or byte, string of the given major type, returning where its contents lie.
+    fn string(bytes: &[u8], cursor: &mut usize, major: u8) -> Option<Range<usize>> {
+        let (found, len) = Self::head(bytes, cursor)?;
+        let start = *cursor;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        if found != major || end > bytes.len() {
+            return None;
+        }
+        *cursor = end;
+        Some(start..end)
+    }
+}
 
 /// A disk-based key-value store
-pub struct DiskStorage {
+///
+/// Values are written as CBOR, to a file named after the blake3 hash of their key, and sharded
+/// across directories by the leading characters of that hash, i.e. `ab/cd/abcd...`.
+#[derive(Debug)]
+pub struct DiskStorage<T: DataType> {
     root_dir: PathBuf,
+    codec: Codec,
     max_size: usize,
+    size: usize,
+    notifier: Notifier,
+    _marker: PhantomData<T>,
 }
 
-impl DiskStorage {
-    pub fn new(root_dir: PathBuf, max_size: usize) -> Self {
-        DiskStorage { root_dir, max_size }
+impl<T: DataType> DiskStorage<T> {
+    pub fn new(root_dir: PathBuf, max_size: usize) -> Result<Self> {
+        fs::create_dir_all(root_dir.join(STAGING_DIR))?;
+        fs::create_dir_all(root_dir.join(QUARANTINE_DIR))?;
+        // Writes staged before a crash never made it into place, and nothing else will remove them.
+        for entry in fs::read_dir(root_dir.join(STAGING_DIR))? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        let size = Self::scan(&root_dir)?.iter().map(|(_, len)| *len).sum();
+        Ok(DiskStorage {
+            root_dir,
+            codec: Codec::default(),
+            max_size,
+            size,
+            notifier: Notifier::default(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Compresses values written from now on with the given codec.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
     }
 
     fn get_file_path(&self, key: &str) -> PathBuf {
-        self.root_dir.join(key)
+        let hash = blake3::hash(key.as_bytes()).to_hex();
+        let mut path = self.root_dir.to_owned();
+        for shard in 0..SHARD_DEPTH {
+            path.push(&hash[shard * SHARD_WIDTH..(shard + 1) * SHARD_WIDTH]);
+        }
+        path.push(hash.as_str());
+        path
+    }
+
+    fn get_staging_path(&self, key: &str) -> PathBuf {
+        let hash = blake3::hash(key.as_bytes()).to_hex();
+        self.root_dir
+            .join(STAGING_DIR)
+            .join(format!("{}.{:016x}", hash, rand::random::<u64>()))
+    }
+
+    /// Walks the fan-out, returning the path and length of every stored file.
+    fn scan(root_dir: &PathBuf) -> Result<Vec<(std::path::PathBuf, usize)>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![(std::path::PathBuf::from(root_dir.as_os_str()), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if depth < SHARD_DEPTH {
+                    let name = entry.file_name();
+                    if entry.file_type()?.is_dir() && name != STAGING_DIR && name != QUARANTINE_DIR
+                    {
+                        dirs.push((path, depth + 1));
+                    }
+                } else if entry.file_type()?.is_file() {
+                    files.push((path, entry.metadata()?.len() as usize));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Reads back the envelope of every stored file, along with the paths of those which don't
+    /// hold one, which are skipped, and left for a scrub to quarantine. Files which can't be
+    /// opened, e.g. as they were removed since the scan, are skipped too.
+    fn envelopes(&self) -> Result<(Vec<Envelope>, Vec<std::path::PathBuf>)> {
+        let mut envelopes = Vec::new();
+        let mut unreadable = Vec::new();
+        for (path, _) in Self::scan(&self.root_dir)? {
+            let file = match fs::File::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    debug!("Skipping {path:?}, which can't be opened: {e}");
+                    continue;
+                }
+            };
+            match from_reader(file) {
+                Ok(envelope) => envelopes.push(envelope),
+                Err(e) => {
+                    warn!("Skipping {path:?}, which doesn't hold an envelope: {e}");
+                    unreadable.push(path);
+                }
+            }
+        }
+        Ok((envelopes, unreadable))
+    }
+
+    /// Reads back the key of every stored file.
+    fn keys(&self) -> Result<Vec<String>> {
+        Ok(self
+            .envelopes()?
+            .0
+            .into_iter()
+            .map(|envelope| envelope.key)
+            .collect())
+    }
+
+    async fn load(&self, key: &str) -> Result<Envelope> {
+        let file_path = self.get_file_path(key);
+        let encoded = match async_std::fs::read(&file_path).await {
+            Ok(encoded) => encoded,
+            Err(e) if e.kind() == ErrorKind::NotFound => bail!(DataStoreError::NotFound),
+            Err(e) => return Err(e.into()),
+        };
+        let envelope: Envelope =
+            from_reader(encoded.as_slice()).map_err(|_| DataStoreError::Decode)?;
+        if envelope.key != key {
+            bail!(DataStoreError::NotFound);
+        }
+        Ok(envelope)
+    }
+
+    async fn store(&mut self, envelope: &Envelope) -> Result<()> {
+        let file_path = self.get_file_path(&envelope.key);
+        debug!("Writing to file: {:?}", file_path);
+
+        let mut encoded = Vec::new();
+        into_writer(envelope, &mut encoded).map_err(|e| anyhow!("{e}"))?;
+
+        let previous = Self::file_len(&file_path).await?.unwrap_or(0);
+        if self.size.saturating_sub(previous) + encoded.len() > self.max_size {
+            bail!(DataStoreError::NoSpace);
+        }
+
+        if let Some(parent) = file_path.parent() {
+            async_std::fs::create_dir_all(parent).await?;
+        }
+
+        // Stage the write, and rename it into place, so a crash never leaves a torn file.
+        let staging_path = self.get_staging_path(&envelope.key);
+        let mut file = async_std::fs::File::create(&staging_path).await?;
+        file.write_all(&encoded).await?;
+        file.sync_all().await?;
+        drop(file);
+        if let Err(e) = async_std::fs::rename(&staging_path, &file_path).await {
+            let _ = async_std::fs::remove_file(&staging_path).await;
+            return Err(e.into());
+        }
+        // The rename is only durable once the directory holding the new name is.
+        if let Some(parent) = file_path.parent() {
+            async_std::fs::File::open(parent).await?.sync_all().await?;
+        }
+
+        self.size = self.size.saturating_sub(previous) + encoded.len();
+        Ok(())
+    }
+
+    async fn file_len(path: &PathBuf) -> Result<Option<usize>> {
+        match async_std::fs::metadata(path).await {
+            Ok(metadata) => Ok(Some(metadata.len() as usize)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 }
 
 #[async_trait]
-impl<T: DataType> DataStore<T> for DiskStorage
-where
-    T: serde::Serialize + serde::de::DeserializeOwned + Write + Read + Send + Sync + Clone,
-{
+impl<T: DataType> DataStore<T> for DiskStorage<T> {
     async fn write(&mut self, key: &str, value: &T) -> Result<()> {
-        let file_path = self.get_file_path(&key.to_string());
-        debug!("Writing to file: {:?}", file_path);
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(file_path)?;
-        todo!()
-        // into_writer(&file, value.to_owned()).map_err(|e| anyhow!(e))
+        self.insert(key, value, None).await.map(|_| ())
+    }
+
+    async fn insert(
+        &mut self,
+        key: &str,
+        value: &T,
+        expires: Option<Expiry>,
+    ) -> Result<Vec<(String, T, Option<Expiry>)>> {
+        self.store(&Envelope {
+            key: key.to_owned(),
+            data: self.codec.encode(&value.serialize())?.into(),
+            expires,
+        })
+        .await?;
+        self.notifier.notify(Change::Written(key.to_owned()));
+        Ok(Vec::new())
     }
 
     async fn read(&self, key: &str) -> Result<T> {
-        let file_path = self.get_file_path(&key.to_string());
-        let file = File::open(file_path)?;
-        // from_reader(file).map_err(|e| e.into());
-        todo!()
+        let envelope = self.load(key).await?;
+        if envelope.is_expired() {
+            bail!(DataStoreError::NotFound);
+        }
+        T::deserialize(&Codec::decode(&envelope.data)?)
+    }
+
+    /// Maps the file, returning its data as a view of the mapping when it is stored uncompressed.
+    async fn read_bytes(&self, key: &str) -> Result<Bytes> {
+        let file = match fs::File::open(self.get_file_path(key)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => bail!(DataStoreError::NotFound),
+            Err(e) => return Err(e.into()),
+        };
+        // Files are replaced by renaming over them, never written in place, so the mapping of
+        // the one opened stays valid.
+        let map = unsafe { Mmap::map(&file)? };
+        let layout = Layout::parse(&map).ok_or(DataStoreError::Decode)?;
+        if &map[layout.key.clone()] != key.as_bytes()
+            || layout.expires.is_some_and(|expires| expires.is_past())
+        {
+            bail!(DataStoreError::NotFound);
+        }
+        Codec::decode_bytes(Bytes::from_owner(map).slice(layout.data))
+    }
+
+    async fn expire(&mut self, key: &str, expires: Option<Expiry>) -> Result<()> {
+        let mut envelope = self.load(key).await?;
+        envelope.expires = expires;
+        self.store(&envelope).await
+    }
+
+    async fn expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        Ok(self.load(key).await?.expires)
+    }
+
+    async fn sweep(&mut self) -> Result<Vec<(String, T)>> {
+        let mut swept = Vec::new();
+        for envelope in self.envelopes()?.0 {
+            if !envelope.is_expired() {
+                continue;
+            }
+            let file_path = self.get_file_path(&envelope.key);
+            if let Some(len) = Self::file_len(&file_path).await? {
+                async_std::fs::remove_file(file_path).await?;
+                self.size = self.size.saturating_sub(len);
+            }
+            self.notifier
+                .notify(Change::Expired(envelope.key.to_owned()));
+            match Codec::decode(&envelope.data).and_then(|data| T::deserialize(&data)) {
+                Ok(value) => swept.push((envelope.key, value)),
+                // It has expired all the same, so goes, though it can't be handed back.
+                Err(e) => warn!("Swept {:?}, which couldn't be read: {e}", envelope.key),
+            }
+        }
+        Ok(swept)
     }
 
     async fn delete(&mut self, key: &str) -> Result<()> {
-        let file_path = self.get_file_path(&key.to_string());
+        let file_path = self.get_file_path(key);
+        let Some(len) = Self::file_len(&file_path).await? else {
+            bail!(DataStoreError::NotFound);
+        };
         async_std::fs::remove_file(file_path).await?;
-        todo!()
+        self.size = self.size.saturating_sub(len);
+        self.notifier.notify(Change::Deleted(key.to_owned()));
+        Ok(())
+    }
+
+    async fn list(&self, options: &ListOptions) -> Result<Vec<String>> {
+        Ok(options.page(self.keys()?))
+    }
+
+    /// Looks the key, and expiry, up in the envelope, without decoding, or reading, its data.
+    async fn contains(&self, key: &str) -> Result<bool> {
+        let file = match fs::File::open(self.get_file_path(key)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let map = unsafe { Mmap::map(&file)? };
+        let layout = Layout::parse(&map).ok_or(DataStoreError::Decode)?;
+        Ok(&map[layout.key] == key.as_bytes()
+            && !layout.expires.is_some_and(|expires| expires.is_past()))
     }
 
-    async fn list(&self, key: Option<&str>) -> Result<Vec<&str>> {
-        todo!()
+    async fn len(&self) -> Result<usize> {
+        Ok(Self::scan(&self.root_dir)?.len())
+    }
+
+    async fn size(&self) -> Result<u64> {
+        Ok(self.size as u64)
+    }
+
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        self.keys()?.iter().for_each(|key| filter.insert(key));
+        Ok(())
+    }
+
+    async fn on_change(&self) -> Result<Changes> {
+        Ok(self.notifier.subscribe())
+    }
+
+    /// Moves the file into the quarantine directory, where it is kept for inspection.
+    async fn quarantine(&mut self, key: &str) -> Result<()> {
+        let file_path = self.get_file_path(key);
+        let Some(len) = Self::file_len(&file_path).await? else {
+            bail!(DataStoreError::NotFound);
+        };
+        let hash = blake3::hash(key.as_bytes()).to_hex();
+        async_std::fs::rename(
+            &file_path,
+            self.root_dir.join(QUARANTINE_DIR).join(hash.as_str()),
+        )
+        .await?;
+        self.size = self.size.saturating_sub(len);
+        self.notifier.notify(Change::Deleted(key.to_owned()));
+        Ok(())
+    }
+
+    /// Moves files which don't hold an envelope into the quarantine directory, under the same
+    /// name, the hash of the key they were written under.
+    async fn quarantine_unreadable(&mut self) -> Result<Vec<String>> {
+        let mut quarantined = Vec::new();
+        for path in self.envelopes()?.1 {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let len = fs::metadata(&path)?.len() as usize;
+            fs::rename(&path, self.root_dir.join(QUARANTINE_DIR).join(name))?;
+            self.size = self.size.saturating_sub(len);
+            quarantined.push(path.display().to_string());
+        }
+        Ok(quarantined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Block;
+
+    #[tokio::test]
+    async fn unreadable_files_are_skipped_then_quarantined() {
+        let dir = std::env::temp_dir().join(format!("gra-disk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = DiskStorage::<Block>::new(dir.into(), 1 << 20).unwrap();
+        store
+            .write("a", &Block::Bytes(vec![[1; 32]]))
+            .await
+            .unwrap();
+        store
+            .write("b", &Block::Bytes(vec![[2; 32]]))
+            .await
+            .unwrap();
+        fs::write(store.get_file_path("b"), b"not an envelope").unwrap();
+
+        assert_eq!(store.list(&ListOptions::default()).await.unwrap(), ["a"]);
+        assert_eq!(store.quarantine_unreadable().await.unwrap().len(), 1);
+        assert_eq!(store.len().await.unwrap(), 1);
+        assert!(store.quarantine_unreadable().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_values_are_not_contained() {
+        let dir = std::env::temp_dir().join(format!("gra-disk-expiry-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = DiskStorage::<Block>::new(dir.into(), 1 << 20).unwrap();
+        let block = Block::Bytes(vec![[1; 32]]);
+        store.insert("a", &block, None).await.unwrap();
+        store
+            .insert("b", &block, Expiry::from_secs(1))
+            .await
+            .unwrap();
+
+        assert!(store.contains("a").await.unwrap());
+        assert!(!store.contains("b").await.unwrap());
+        assert!(!store.contains("c").await.unwrap());
     }
 }
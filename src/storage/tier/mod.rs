@@ -1,60 +1,166 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
-use super::{DataStore, DataType, Storage};
+use super::{Codec, DataStore, DataStoreError, DataType, Storage};
+use crate::node::Client;
+
+mod config;
+pub use config::{Eviction, TierConfig};
 
 mod disk;
-use disk::DiskStorage;
+pub use disk::DiskStorage;
+
+mod pack;
+pub use pack::PackStorage;
 
-mod memory;
-pub use memory::MemoryStorage;
+mod process;
+pub use process::ProcessStorage;
+
+mod shm;
+pub use shm::ShmStorage;
 
 mod remote;
-use remote::RemoteStorage;
+pub use remote::RemoteStorage;
+
+const PROCESS_MAX_SIZE: usize = 4096;
+const SHM_MAX_SIZE: usize = 16 << 20;
+const DISK_MAX_SIZE: usize = 1 << 30;
+const PACK_MAX_SIZE: usize = 1 << 30;
+
+/// Shared memory favours the speed of lz4, while disk favours the ratio of zstd.
+const SHM_CODEC: Codec = Codec::Lz4;
+const DISK_CODEC: Codec = Codec::Zstd;
+const PACK_CODEC: Codec = Codec::Zstd;
 
 #[derive(Debug, Clone)]
 pub enum Tier {
-    Memory,
-    // Disk,
-    // Remote,
+    /// An in-process tier, private to the current process.
+    Process,
+    /// A tier in /dev/shm, shared by every process on the host.
+    Shm,
+    /// A persistent tier, rooted at the given directory.
+    Disk(PathBuf),
+    /// A persistent tier of pack files, rooted at the given directory.
+    Pack(PathBuf),
+    /// The network, as the bottom tier, reached through the given node client.
+    Remote(Client),
 }
 
 impl Tier {
     pub fn new() -> Self {
-        Tier::Memory
+        Tier::Process
     }
 
-    pub fn from_str(s: &str) -> Self {
-        match s {
-            "Memory" => Tier::Memory,
-            // "Disk" => Tier::Disk,
-            // "Remote" => Tier::Remote,
-            _ => Tier::Memory,
+    /// Parses a local tier, failing with `Invalid` for an unknown one. A `Remote` tier needs a
+    /// client, so it is only built through `TierConfig`.
+    pub fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("Disk:") {
+            return Ok(Tier::Disk(PathBuf::from(path)));
+        }
+        if let Some(path) = s.strip_prefix("Pack:") {
+            return Ok(Tier::Pack(PathBuf::from(path)));
         }
+        Ok(match s {
+            "Process" => Tier::Process,
+            "Shm" => Tier::Shm,
+            "Disk" => Tier::Disk(Self::default_disk_root()),
+            "Pack" => Tier::Pack(Self::default_disk_root().join("packs")),
+            _ => bail!(DataStoreError::Invalid),
+        })
     }
 
     pub fn to_str(&self) -> &str {
         match self {
-            Tier::Memory => "Memory",
-            // Tier::Disk => "Disk",
-            // Tier::Remote => "Remote",
+            Tier::Process => "Process",
+            Tier::Shm => "Shm",
+            Tier::Disk(_) => "Disk",
+            Tier::Pack(_) => "Pack",
+            Tier::Remote(_) => "Remote",
         }
     }
+
+    /// The directory used by `Tier::Disk` when none is given, `$HOME/.gra`.
+    pub fn default_disk_root() -> PathBuf {
+        std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join(".gra")
+    }
 }
 
-impl<T: DataType> Into<Storage<T>> for Tier {
-    fn into(self) -> Storage<T> {
-        match self {
-            Tier::Memory => Storage::Memory(MemoryStorage::new(4096)),
-            // Tier::Disk => Storage::Disk(DiskStorage::new(4096)),
-            // Tier::Remote => Storage::Remote(RemoteStorage::new(4096)),
-        }
+impl<T: DataType> TryFrom<TierConfig> for Storage<T> {
+    type Error = anyhow::Error;
+
+    /// Opens the tier, failing with `Invalid` for a policy it does not support.
+    fn try_from(config: TierConfig) -> Result<Self> {
+        let TierConfig {
+            tier,
+            max_size,
+            codec,
+            eviction,
+            ..
+        } = config;
+        Ok(match tier {
+            Tier::Process => {
+                if codec.is_some() {
+                    bail!(DataStoreError::Invalid);
+                }
+                Storage::Process(
+                    ProcessStorage::new(max_size.unwrap_or(PROCESS_MAX_SIZE))
+                        .with_eviction(eviction.unwrap_or(Eviction::Lru)),
+                )
+            }
+            Tier::Shm => Storage::Shm(
+                ShmStorage::new(
+                    ShmStorage::<T>::default_path(),
+                    max_size.unwrap_or(SHM_MAX_SIZE),
+                )?
+                .with_codec(codec.unwrap_or(SHM_CODEC))
+                .with_eviction(eviction.unwrap_or(Eviction::Fifo))?,
+            ),
+            Tier::Disk(root) => {
+                if eviction.is_some_and(|eviction| eviction != Eviction::Reject) {
+                    bail!(DataStoreError::Invalid);
+                }
+                Storage::Disk(
+                    DiskStorage::new(
+                        root.join(T::NAMESPACE).into(),
+                        max_size.unwrap_or(DISK_MAX_SIZE),
+                    )?
+                    .with_codec(codec.unwrap_or(DISK_CODEC)),
+                )
+            }
+            Tier::Pack(root) => {
+                if eviction.is_some_and(|eviction| eviction != Eviction::Reject) {
+                    bail!(DataStoreError::Invalid);
+                }
+                Storage::Pack(
+                    PackStorage::new(root.join(T::NAMESPACE), max_size.unwrap_or(PACK_MAX_SIZE))?
+                        .with_codec(codec.unwrap_or(PACK_CODEC)),
+                )
+            }
+            Tier::Remote(client) => {
+                if max_size.is_some() || codec.is_some() || eviction.is_some() {
+                    bail!(DataStoreError::Invalid);
+                }
+                Storage::Remote(RemoteStorage::new(client))
+            }
+        })
+    }
+}
+
+impl<T: DataType> TryFrom<Tier> for Storage<T> {
+    type Error = anyhow::Error;
+
+    fn try_from(tier: Tier) -> Result<Self> {
+        TierConfig::from(tier).try_into()
     }
 }
 
 impl Default for Tier {
     fn default() -> Self {
-        Tier::Memory
+        Tier::Process
     }
 }
@@ -0,0 +1,690 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use ciborium::{from_reader, into_writer};
+use hashbrown::HashMap;
+use memmap2::MmapOptions;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    marker::PhantomData,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+};
+use tracing::{debug, info, warn};
+
+use crate::storage::{
+    Change, Changes, Codec, DataStoreError, DataType, Expiry, Filter, ListOptions, Notifier,
+};
+
+use super::DataStore;
+
+const SEGMENT_EXTENSION: &str = "pack";
+const INDEX_FILE: &str = "index";
+
+/// Segments are sealed, and the next one started, once appending would grow them past this.
+const SEGMENT_MAX_LEN: u64 = 64 << 20;
+
+/// The index is checkpointed after this many appends, bounding the tail replayed on recovery.
+const CHECKPOINT_INTERVAL: usize = 1024;
+
+/// Sealed segments are rewritten once at least this fraction of them is garbage.
+const COMPACTION_THRESHOLD: f64 = 0.5;
+
+/// Record layout: liveness flag, key length, data length, expiry, the key and encoded data, then
+/// a truncated blake3 checksum of all that precedes it, so a torn append is detected.
+const RECORD_HEADER_LEN: usize = 1 + 2 + 4 + 8;
+const CHECKSUM_LEN: usize = 8;
+const LIVE: u8 = 1;
+const TOMBSTONE: u8 = 0;
+
+/// Where a record lives, the length of the whole record, and when its value expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Location {
+    segment: u32,
+    offset: u64,
+    len: u32,
+    #[serde(default)]
+    expires: Option<Expiry>,
+}
+
+impl Location {
+    fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires.is_past())
+    }
+}
+
+/// The locations of the live records, along with the end of the log they account for.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    segment: u32,
+    offset: u64,
+    locations: HashMap<String, Location>,
+}
+
+/// A record parsed from a segment.
+struct Record<'a> {
+    live: bool,
+    expires: Option<Expiry>,
+    key: &'a [u8],
+    data: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Record<'a> {
+    fn encode(flag: u8, key: &str, data: &[u8], expires: Option<Expiry>) -> Vec<u8> {
+        let mut record =
+            Vec::with_capacity(RECORD_HEADER_LEN + key.len() + data.len() + CHECKSUM_LEN);
+        record.push(flag);
+        record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&Expiry::to_secs(expires).to_le_bytes());
+        record.extend_from_slice(key.as_bytes());
+        record.extend_from_slice(data);
+        let checksum = blake3::hash(&record);
+        record.extend_from_slice(&checksum.as_bytes()[..CHECKSUM_LEN]);
+        record
+    }
+
+    /// Parses the record at the start of the bytes, or None if it is truncated, or corrupt.
+    fn decode(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.len() < RECORD_HEADER_LEN {
+            return None;
+        }
+        let key_len = u16::from_le_bytes([bytes[1], bytes[2]]) as usize;
+        let data_len = u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]) as usize;
+        let expires = u64::from_le_bytes(bytes[7..15].try_into().expect("slice is 8 bytes"));
+        let key_end = RECORD_HEADER_LEN + key_len;
+        let data_end = key_end + data_len;
+        let len = data_end + CHECKSUM_LEN;
+        if bytes.len() < len
+            || blake3::hash(&bytes[..data_end]).as_bytes()[..CHECKSUM_LEN] != bytes[data_end..len]
+        {
+            return None;
+        }
+        Some(Record {
+            live: bytes[0] == LIVE,
+            expires: Expiry::from_secs(expires),
+            key: &bytes[RECORD_HEADER_LEN..key_end],
+            data: &bytes[key_end..data_end],
+            len,
+        })
+    }
+
+    fn key(&self) -> String {
+        String::from_utf8_lossy(self.key).to_string()
+    }
+}
+
+/// A log-structured store, packing many small values into a few large segment files.
+///
+/// Writes, and deletes as tombstones, are appended to the active segment, while an index maps
+/// each key to its latest record. The index is checkpointed to disk periodically, and on open,
+/// the log past the checkpoint is replayed, skipping corrupt records, and truncating any torn
+/// record at its tail. Sealed segments that are mostly garbage are compacted, by appending their
+/// live records anew.
+#[derive(Debug)]
+pub struct PackStorage<T: DataType> {
+    root_dir: PathBuf,
+    codec: Codec,
+    index: Index,
+    active: File,
+    /// Bytes of each segment no longer referenced by the index.
+    garbage: HashMap<u32, u64>,
+    appended: usize,
+    max_size: usize,
+    size: usize,
+    notifier: Notifier,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DataType> PackStorage<T> {
+    pub fn new(root_dir: PathBuf, max_size: usize) -> Result<Self> {
+        fs::create_dir_all(&root_dir)?;
+        let mut index = Self::load_index(&root_dir);
+        let segments = Self::segments(&root_dir)?;
+        if index.offset > 0 && !segments.contains(&index.segment) {
+            warn!("Pack index refers to a missing segment, replaying the whole log");
+            index = Index::default();
+        }
+
+        let checkpointed = index.segment;
+        let last = segments.last().copied();
+        for &segment in segments.iter().filter(|&&segment| segment >= checkpointed) {
+            let start = if segment == index.segment {
+                index.offset
+            } else {
+                0
+            };
+            index.offset = Self::replay(
+                &root_dir,
+                segment,
+                start,
+                Some(segment) == last,
+                &mut index.locations,
+            )?;
+            index.segment = segment;
+        }
+        index.segment = index.segment.max(1);
+
+        let mut garbage: HashMap<u32, u64> = HashMap::new();
+        for &segment in &segments {
+            let len = fs::metadata(Self::segment_path(&root_dir, segment))?.len();
+            garbage.insert(segment, len);
+        }
+        for location in index.locations.values() {
+            if let Some(dead) = garbage.get_mut(&location.segment) {
+                *dead -= location.len as u64;
+            }
+        }
+
+        let active = Self::open_segment(&root_dir, index.segment)?;
+        let size = index
+            .locations
+            .values()
+            .map(|location| location.len as usize)
+            .sum();
+        let mut storage = PackStorage {
+            root_dir,
+            codec: Codec::default(),
+            index,
+            active,
+            garbage,
+            appended: 0,
+            max_size,
+            size,
+            notifier: Notifier::default(),
+            _marker: PhantomData,
+        };
+        storage.checkpoint()?;
+        Ok(storage)
+    }
+
+    /// Compresses values written from now on with the given codec.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    fn segment_path(root_dir: &PathBuf, segment: u32) -> PathBuf {
+        root_dir.join(format!("{segment:08}.{SEGMENT_EXTENSION}"))
+    }
+
+    fn open_segment(root_dir: &PathBuf, segment: u32) -> Result<File> {
+        Ok(OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::segment_path(root_dir, segment))?)
+    }
+
+    /// The numbers of every segment, in the order they were written.
+    fn segments(root_dir: &PathBuf) -> Result<Vec<u32>> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(root_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+                if let Some(segment) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+                {
+                    segments.push(segment);
+                }
+            }
+        }
+        segments.sort_unstable();
+        Ok(segments)
+    }
+
+    /// Loads the checkpointed index, or an empty one, which replays the whole log.
+    fn load_index(root_dir: &PathBuf) -> Index {
+        match fs::File::open(root_dir.join(INDEX_FILE)) {
+            Ok(file) => from_reader(file).unwrap_or_else(|e| {
+                warn!("Pack index is unreadable, replaying the whole log: {e}");
+                Index::default()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Index::default(),
+            Err(e) => {
+                warn!("Failed to open pack index, replaying the whole log: {e}");
+                Index::default()
+            }
+        }
+    }
+
+    /// The record at the offset or, if that is corrupt, the first intact one past it, along with
+    /// the offset it starts at.
+    fn next_record(bytes: &[u8], offset: usize) -> Option<(usize, Record)> {
+        (offset..bytes.len())
+            .find_map(|offset| Record::decode(&bytes[offset..]).map(|record| (offset, record)))
+    }
+
+    /// Applies the records of a segment, from the given offset, returning where they end.
+    ///
+    /// Corrupt records are reported, and skipped. Only the last segment, the one appended to, is
+    /// truncated, to drop a torn record at its tail, while the corrupt tail of a sealed segment is
+    /// left be.
+    fn replay(
+        root_dir: &PathBuf,
+        segment: u32,
+        start: u64,
+        last: bool,
+        locations: &mut HashMap<String, Location>,
+    ) -> Result<u64> {
+        let path = Self::segment_path(root_dir, segment);
+        let bytes = fs::read(&path)?;
+        let mut offset = start as usize;
+        while let Some((at, record)) = Self::next_record(&bytes, offset) {
+            if at > offset {
+                warn!(
+                    "Skipping {} corrupt bytes at {offset} of {path:?}",
+                    at - offset
+                );
+            }
+            let key = record.key();
+            if record.live {
+                locations.insert(
+                    key,
+                    Location {
+                        segment,
+                        offset: at as u64,
+                        len: record.len as u32,
+                        expires: record.expires,
+                    },
+                );
+            } else {
+                locations.remove(&key);
+            }
+            offset = at + record.len;
+        }
+        if offset < bytes.len() && last {
+            warn!(
+                "Truncating {} bytes from the tail of {path:?}",
+                bytes.len() - offset
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(offset as u64)?;
+        } else if offset < bytes.len() {
+            warn!(
+                "Leaving {} corrupt bytes at the tail of sealed {path:?}",
+                bytes.len() - offset
+            );
+        }
+        debug!("Replayed {path:?} from {start} to {offset}");
+        Ok(offset as u64)
+    }
+
+    /// Writes the index, atomically, so that recovery only replays what follows.
+    fn checkpoint(&mut self) -> Result<()> {
+        let mut encoded = Vec::new();
+        into_writer(&self.index, &mut encoded).map_err(|e| anyhow!("{e}"))?;
+        let staging_path = self.root_dir.join(format!("{INDEX_FILE}.tmp"));
+        let mut file = File::create(&staging_path)?;
+        file.write_all(&encoded)?;
+        file.sync_all()?;
+        fs::rename(staging_path, self.root_dir.join(INDEX_FILE))?;
+        // The rename is only durable once the directory holding the new name is.
+        File::open(&self.root_dir)?.sync_all()?;
+        self.appended = 0;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &[u8], expires: Option<Expiry>) -> Result<Location> {
+        if self.index.offset > 0 && self.index.offset + record.len() as u64 > SEGMENT_MAX_LEN {
+            self.index.segment += 1;
+            self.index.offset = 0;
+            self.active = Self::open_segment(&self.root_dir, self.index.segment)?;
+            File::open(&self.root_dir)?.sync_all()?;
+            debug!("Started segment {}", self.index.segment);
+        }
+        self.active.write_all(record)?;
+        self.active.sync_data()?;
+
+        let location = Location {
+            segment: self.index.segment,
+            offset: self.index.offset,
+            len: record.len() as u32,
+            expires,
+        };
+        self.index.offset += record.len() as u64;
+        self.appended += 1;
+        if self.appended >= CHECKPOINT_INTERVAL {
+            self.checkpoint()?;
+        }
+        Ok(location)
+    }
+
+    fn discard(&mut self, location: Location) {
+        *self.garbage.entry(location.segment).or_default() += location.len as u64;
+        self.size -= location.len as usize;
+    }
+
+    fn read_record(&self, location: &Location) -> Result<Vec<u8>> {
+        let file = File::open(Self::segment_path(&self.root_dir, location.segment))?;
+        let mut record = vec![0; location.len as usize];
+        file.read_exact_at(&mut record, location.offset)?;
+        Ok(record)
+    }
+
+    /// Reads the encoded data of the record at the location, checking it belongs to the key.
+    fn read_data(&self, key: &str, location: &Location) -> Result<Vec<u8>> {
+        let bytes = self.read_record(location)?;
+        let Some(record) = Record::decode(&bytes).filter(|record| record.key == key.as_bytes())
+        else {
+            bail!(DataStoreError::Corrupt);
+        };
+        Ok(record.data.to_vec())
+    }
+
+    /// Maps the record at the location, returning a view of its encoded data, checked against the
+    /// key, so that large values are read without being copied.
+    fn map_data(&self, key: &str, location: &Location) -> Result<Bytes> {
+        let file = File::open(Self::segment_path(&self.root_dir, location.segment))?;
+        // Segments are only ever appended to, or removed whole, so the mapping stays valid.
+        let map = unsafe {
+            MmapOptions::new()
+                .offset(location.offset)
+                .len(location.len as usize)
+                .map(&file)?
+        };
+        let Some(record) = Record::decode(&map).filter(|record| record.key == key.as_bytes())
+        else {
+            bail!(DataStoreError::Corrupt);
+        };
+        let start = RECORD_HEADER_LEN + record.key.len();
+        let end = start + record.data.len();
+        Ok(Bytes::from_owner(map).slice(start..end))
+    }
+
+    /// Appends a tombstone for the key, and drops it from the index.
+    fn remove(&mut self, key: &str, location: Location) -> Result<()> {
+        let tombstone = self.append(&Record::encode(TOMBSTONE, key, &[], None), None)?;
+        self.index.locations.remove(key);
+        self.discard(location);
+        // Tombstones are garbage from the start, kept only until compaction.
+        *self.garbage.entry(tombstone.segment).or_default() += tombstone.len as u64;
+        Ok(())
+    }
+
+    /// Compacts, after a write which is already durable, so logging, rather than returning, a
+    /// failure, which the next compaction retries.
+    fn compact_after_write(&mut self) {
+        if let Err(e) = self.compact() {
+            warn!("Failed to compact {:?}: {e}", self.root_dir);
+        }
+    }
+
+    /// Rewrites every sealed segment which is mostly garbage, returning how many were.
+    pub fn compact(&mut self) -> Result<usize> {
+        let mut candidates = Vec::new();
+        for (&segment, &garbage) in &self.garbage {
+            if segment >= self.index.segment || garbage == 0 {
+                continue;
+            }
+            let len = fs::metadata(Self::segment_path(&self.root_dir, segment))?.len();
+            if len == 0 || garbage as f64 / len as f64 >= COMPACTION_THRESHOLD {
+                candidates.push(segment);
+            }
+        }
+        candidates.sort_unstable();
+        for &segment in &candidates {
+            self.compact_segment(segment)?;
+        }
+        Ok(candidates.len())
+    }
+
+    /// Appends the live records of the segment anew, then removes it.
+    ///
+    /// While an older segment remains, it may hold a record a tombstone deletes, so tombstones of
+    /// keys not written since are carried forward too, lest a replay of the whole log resurrect
+    /// them. Once the oldest segment is compacted, its tombstones shadow nothing, and are dropped.
+    fn compact_segment(&mut self, segment: u32) -> Result<()> {
+        let path = Self::segment_path(&self.root_dir, segment);
+        let bytes = fs::read(&path)?;
+        let shadows = Self::segments(&self.root_dir)?
+            .first()
+            .is_some_and(|&oldest| oldest < segment);
+        let mut offset = 0;
+        let mut moved = 0;
+        while let Some((at, record)) = Self::next_record(&bytes, offset) {
+            if at > offset {
+                warn!(
+                    "Dropping {} corrupt bytes at {offset} of {path:?}",
+                    at - offset
+                );
+            }
+            offset = at;
+            let key = record.key();
+            let current = self.index.locations.get(&key).is_some_and(|location| {
+                location.segment == segment && location.offset == offset as u64
+            });
+            if record.live && current {
+                let location = self.append(&bytes[offset..offset + record.len], record.expires)?;
+                self.index.locations.insert(key, location);
+                moved += 1;
+            } else if !record.live && shadows && !self.index.locations.contains_key(&key) {
+                let tombstone = self.append(&bytes[offset..offset + record.len], None)?;
+                *self.garbage.entry(tombstone.segment).or_default() += tombstone.len as u64;
+                moved += 1;
+            }
+            offset += record.len;
+        }
+
+        // The index must stop referring to the segment before it is removed.
+        self.checkpoint()?;
+        fs::remove_file(&path)?;
+        self.garbage.remove(&segment);
+        info!("Compacted segment {segment}, keeping {moved} records");
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: DataType> DataStore<T> for PackStorage<T> {
+    async fn write(&mut self, key: &str, value: &T) -> Result<()> {
+        self.insert(key, value, None).await.map(|_| ())
+    }
+
+    async fn insert(
+        &mut self,
+        key: &str,
+        value: &T,
+        expires: Option<Expiry>,
+    ) -> Result<Vec<(String, T, Option<Expiry>)>> {
+        if key.len() > u16::MAX as usize {
+            bail!(DataStoreError::Invalid);
+        }
+        let record = Record::encode(LIVE, key, &self.codec.encode(&value.serialize())?, expires);
+        let previous = self.index.locations.get(key).copied();
+        let previous_len = previous.map_or(0, |location| location.len as usize);
+        if self.size - previous_len + record.len() > self.max_size {
+            bail!(DataStoreError::NoSpace);
+        }
+
+        let location = self.append(&record, expires)?;
+        if let Some(previous) = previous {
+            self.discard(previous);
+        }
+        self.index.locations.insert(key.to_owned(), location);
+        self.size += record.len();
+        self.notifier.notify(Change::Written(key.to_owned()));
+        if previous.is_some() {
+            self.compact_after_write();
+        }
+        Ok(Vec::new())
+    }
+
+    async fn read(&self, key: &str) -> Result<T> {
+        let Some(location) = self
+            .index
+            .locations
+            .get(key)
+            .filter(|location| !location.is_expired())
+        else {
+            bail!(DataStoreError::NotFound);
+        };
+        T::deserialize(&Codec::decode(&self.read_data(key, location)?)?)
+    }
+
+    async fn read_bytes(&self, key: &str) -> Result<Bytes> {
+        let Some(location) = self
+            .index
+            .locations
+            .get(key)
+            .filter(|location| !location.is_expired())
+        else {
+            bail!(DataStoreError::NotFound);
+        };
+        Codec::decode_bytes(self.map_data(key, location)?)
+    }
+
+    async fn expire(&mut self, key: &str, expires: Option<Expiry>) -> Result<()> {
+        let Some(&location) = self.index.locations.get(key) else {
+            bail!(DataStoreError::NotFound);
+        };
+        let record = Record::encode(LIVE, key, &self.read_data(key, &location)?, expires);
+        let relocated = self.append(&record, expires)?;
+        self.discard(location);
+        self.index.locations.insert(key.to_owned(), relocated);
+        self.size += record.len();
+        Ok(())
+    }
+
+    async fn expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        let Some(location) = self.index.locations.get(key) else {
+            bail!(DataStoreError::NotFound);
+        };
+        Ok(location.expires)
+    }
+
+    async fn sweep(&mut self) -> Result<Vec<(String, T)>> {
+        let expired: Vec<(String, Location)> = self
+            .index
+            .locations
+            .iter()
+            .filter(|(_, location)| location.is_expired())
+            .map(|(key, location)| (key.to_owned(), *location))
+            .collect();
+        let mut swept = Vec::with_capacity(expired.len());
+        for (key, location) in expired {
+            let value = self
+                .read_data(&key, &location)
+                .and_then(|data| Codec::decode(&data))
+                .and_then(|data| T::deserialize(&data));
+            self.remove(&key, location)?;
+            self.notifier.notify(Change::Expired(key.to_owned()));
+            match value {
+                Ok(value) => swept.push((key, value)),
+                // It has expired all the same, so goes, though it can't be handed back.
+                Err(e) => warn!("Swept {key:?}, which couldn't be read: {e}"),
+            }
+        }
+        if !swept.is_empty() {
+            self.compact_after_write();
+        }
+        Ok(swept)
+    }
+
+    async fn delete(&mut self, key: &str) -> Result<()> {
+        let Some(&location) = self.index.locations.get(key) else {
+            bail!(DataStoreError::NotFound);
+        };
+        self.remove(key, location)?;
+        self.notifier.notify(Change::Deleted(key.to_owned()));
+        self.compact_after_write();
+        Ok(())
+    }
+
+    async fn list(&self, options: &ListOptions) -> Result<Vec<String>> {
+        Ok(options.page(
+            self.index
+                .locations
+                .iter()
+                .filter(|(_, location)| !location.is_expired())
+                .map(|(key, _)| key.to_owned()),
+        ))
+    }
+
+    async fn contains(&self, key: &str) -> Result<bool> {
+        Ok(self
+            .index
+            .locations
+            .get(key)
+            .is_some_and(|location| !location.is_expired()))
+    }
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.index.locations.len())
+    }
+
+    async fn size(&self) -> Result<u64> {
+        Ok(self.size as u64)
+    }
+
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        self.index
+            .locations
+            .keys()
+            .for_each(|key| filter.insert(key));
+        Ok(())
+    }
+
+    async fn on_change(&self) -> Result<Changes> {
+        Ok(self.notifier.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Block;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gra-pack-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn value(byte: u8) -> Block {
+        Block::Bytes(vec![[byte; 32]])
+    }
+
+    async fn filled(dir: &PathBuf) -> Vec<u8> {
+        let mut store = PackStorage::<Block>::new(dir.to_owned(), 1 << 20).unwrap();
+        for (key, byte) in [("a", 1), ("b", 2), ("c", 3)] {
+            store.insert(key, &value(byte), None).await.unwrap();
+        }
+        fs::remove_file(dir.join(INDEX_FILE)).unwrap();
+        fs::read(PackStorage::<Block>::segment_path(dir, 1)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn corruption_in_a_sealed_segment_is_skipped() {
+        let dir = dir("sealed");
+        let mut bytes = filled(&dir).await;
+        bytes[RECORD_HEADER_LEN + 1] ^= 1;
+        let sealed = PackStorage::<Block>::segment_path(&dir, 1);
+        fs::write(&sealed, &bytes).unwrap();
+        File::create(PackStorage::<Block>::segment_path(&dir, 2)).unwrap();
+
+        let store = PackStorage::<Block>::new(dir.to_owned(), 1 << 20).unwrap();
+        assert!(store.read("a").await.is_err());
+        assert_eq!(store.read("b").await.unwrap(), value(2));
+        assert_eq!(store.read("c").await.unwrap(), value(3));
+        assert_eq!(fs::read(&sealed).unwrap(), bytes);
+    }
+
+    #[tokio::test]
+    async fn torn_tail_of_the_last_segment_is_truncated() {
+        let dir = dir("torn");
+        let bytes = filled(&dir).await;
+        let active = PackStorage::<Block>::segment_path(&dir, 1);
+        fs::write(&active, [&bytes[..], &[LIVE, 1, 0]].concat()).unwrap();
+
+        let store = PackStorage::<Block>::new(dir.to_owned(), 1 << 20).unwrap();
+        assert_eq!(store.read("c").await.unwrap(), value(3));
+        assert_eq!(fs::read(&active).unwrap(), bytes);
+    }
+}
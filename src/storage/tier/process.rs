@@ -0,0 +1,248 @@
+use anyhow::{bail, Result};
+use async_std::sync::RwLock;
+use async_trait::async_trait;
+use ciborium_io::{Read, Write};
+use hashbrown::HashMap;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::storage::{
+    Change, Changes, DataStoreError, DataType, Expiry, Filter, ListOptions, Notifier,
+};
+
+use super::{DataStore, Eviction};
+
+/// A value held in memory, along with its serialized size, expiry, and the ticks of its write,
+/// and of its last use.
+#[derive(Debug)]
+struct Slot<T> {
+    value: T,
+    size: usize,
+    expires: Option<Expiry>,
+    written: u64,
+    used: AtomicU64,
+}
+
+impl<T> Slot<T> {
+    fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires.is_past())
+    }
+}
+
+/// An in-memory, size bounded, store which evicts values when full, by default the least
+/// recently used.
+#[derive(Debug)]
+pub struct ProcessStorage<T: DataType> {
+    data: RwLock<HashMap<String, Slot<T>>>,
+    /// The keys, by the tick the eviction policy ranks them by, so the next victim is the first.
+    order: Mutex<BTreeMap<u64, String>>,
+    max_size: usize,
+    size: usize,
+    eviction: Eviction,
+    clock: AtomicU64,
+    notifier: Notifier,
+}
+
+impl<T: DataType> ProcessStorage<T> {
+    pub fn new(max_size: usize) -> Self {
+        ProcessStorage {
+            data: RwLock::new(HashMap::new()),
+            order: Mutex::new(BTreeMap::new()),
+            max_size,
+            size: 0,
+            eviction: Eviction::Lru,
+            clock: AtomicU64::new(0),
+            notifier: Notifier::default(),
+        }
+    }
+
+    /// Makes room with the given policy from now on.
+    pub fn with_eviction(mut self, eviction: Eviction) -> Self {
+        self.eviction = eviction;
+        self
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The tick the slot is ranked by, its last use, or its write, if uses don't count.
+    fn rank(&self, slot: &Slot<T>) -> u64 {
+        match self.eviction {
+            Eviction::Lru => slot.used.load(Ordering::Relaxed),
+            _ => slot.written,
+        }
+    }
+
+    fn unrank(&mut self, slot: &Slot<T>) {
+        let rank = self.rank(slot);
+        self.order.get_mut().expect("order lock").remove(&rank);
+    }
+}
+
+#[async_trait]
+impl<T: DataType> DataStore<T> for ProcessStorage<T> {
+    async fn write(&mut self, key: &str, value: &T) -> Result<()> {
+        self.insert(key, value, None).await.map(|_| ())
+    }
+
+    async fn insert(
+        &mut self,
+        key: &str,
+        value: &T,
+        expires: Option<Expiry>,
+    ) -> Result<Vec<(String, T, Option<Expiry>)>> {
+        let size = value.serialize().len();
+        if size > self.max_size {
+            bail!(DataStoreError::TooLarge);
+        }
+
+        let used = self.tick();
+        let previous = self.data.get_mut().get(key).map_or(0, |slot| slot.size);
+        if self.eviction == Eviction::Reject && self.size - previous + size > self.max_size {
+            bail!(DataStoreError::NoSpace);
+        }
+        if let Some(previous) = self.data.get_mut().remove(key) {
+            self.size -= previous.size;
+            self.unrank(&previous);
+        }
+
+        // Evict the least recently used, or the oldest, values until the new value fits.
+        let mut evicted = Vec::new();
+        while self.size + size > self.max_size {
+            let Some((_, victim)) = self.order.get_mut().expect("order lock").pop_first() else {
+                break;
+            };
+            if let Some(slot) = self.data.get_mut().remove(&victim) {
+                self.size -= slot.size;
+                evicted.push((victim, slot.value, slot.expires));
+            }
+        }
+
+        self.order
+            .get_mut()
+            .expect("order lock")
+            .insert(used, key.to_owned());
+        self.data.get_mut().insert(
+            key.to_owned(),
+            Slot {
+                value: value.to_owned(),
+                size,
+                expires,
+                written: used,
+                used: AtomicU64::new(used),
+            },
+        );
+        self.size += size;
+
+        for (evicted_key, _, _) in &evicted {
+            self.notifier
+                .notify(Change::Evicted(evicted_key.to_owned()));
+        }
+        self.notifier.notify(Change::Written(key.to_owned()));
+        Ok(evicted)
+    }
+
+    async fn read(&self, key: &str) -> Result<T> {
+        let data = self.data.read().await;
+        let slot = data
+            .get(key)
+            .filter(|slot| !slot.is_expired())
+            .ok_or(DataStoreError::NotFound)?;
+        if self.eviction == Eviction::Lru {
+            // Swapped under the lock, so concurrent reads re-rank the key in the order they used it.
+            let mut order = self.order.lock().expect("order lock");
+            let used = self.tick();
+            order.remove(&slot.used.swap(used, Ordering::Relaxed));
+            order.insert(used, key.to_owned());
+        }
+        Ok(slot.value.clone())
+    }
+
+    async fn delete(&mut self, key: &str) -> Result<()> {
+        let slot = self
+            .data
+            .get_mut()
+            .remove(key)
+            .ok_or(DataStoreError::NotFound)?;
+        self.size -= slot.size;
+        self.unrank(&slot);
+        self.notifier.notify(Change::Deleted(key.to_owned()));
+        Ok(())
+    }
+
+    async fn expire(&mut self, key: &str, expires: Option<Expiry>) -> Result<()> {
+        let slot = self
+            .data
+            .get_mut()
+            .get_mut(key)
+            .ok_or(DataStoreError::NotFound)?;
+        slot.expires = expires;
+        Ok(())
+    }
+
+    async fn expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        let data = self.data.read().await;
+        let slot = data.get(key).ok_or(DataStoreError::NotFound)?;
+        Ok(slot.expires)
+    }
+
+    async fn sweep(&mut self) -> Result<Vec<(String, T)>> {
+        let expired: Vec<String> = self
+            .data
+            .get_mut()
+            .iter()
+            .filter(|(_, slot)| slot.is_expired())
+            .map(|(key, _)| key.to_owned())
+            .collect();
+        let mut swept = Vec::with_capacity(expired.len());
+        for key in expired {
+            if let Some(slot) = self.data.get_mut().remove(&key) {
+                self.size -= slot.size;
+                self.unrank(&slot);
+                self.notifier.notify(Change::Expired(key.to_owned()));
+                swept.push((key, slot.value));
+            }
+        }
+        Ok(swept)
+    }
+
+    async fn list(&self, options: &ListOptions) -> Result<Vec<String>> {
+        Ok(options.page(self.data.read().await.keys().cloned()))
+    }
+
+    async fn contains(&self, key: &str) -> Result<bool> {
+        Ok(self
+            .data
+            .read()
+            .await
+            .get(key)
+            .is_some_and(|slot| !slot.is_expired()))
+    }
+
+    async fn len(&self) -> Result<usize> {
+        Ok(self.data.read().await.len())
+    }
+
+    async fn size(&self) -> Result<u64> {
+        Ok(self.size as u64)
+    }
+
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        self.data
+            .read()
+            .await
+            .keys()
+            .for_each(|key| filter.insert(key));
+        Ok(())
+    }
+
+    async fn on_change(&self) -> Result<Changes> {
+        Ok(self.notifier.subscribe())
+    }
+}
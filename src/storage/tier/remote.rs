@@ -1,37 +1,93 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use ciborium_io::{Read, Write};
 use hashbrown::HashMap;
+use std::marker::PhantomData;
+use tracing::debug;
 
-use crate::node::Node;
+use crate::hash::Hash;
+use crate::models::Block;
+use crate::node::Client;
+use crate::storage::{Change, Changes, DataStoreError, DataType, Expiry, Filter, Notifier};
 
-use super::{DataStore, DataType};
+use super::DataStore;
 
-pub struct RemoteStorage {
-    // node: Node,
+/// The network tier, reading blocks from their providers, and announcing written blocks.
+///
+/// Peers serve blocks, so values are carried in their serialized form. The network can't be
+/// enumerated, and holds nothing locally, so listing, and counting, it is `Unsupported`.
+#[derive(Debug)]
+pub struct RemoteStorage<T: DataType> {
+    client: Client,
+    notifier: Notifier,
+    _marker: PhantomData<T>,
 }
 
-impl RemoteStorage {
-    pub fn new() -> Self {
-        RemoteStorage {}
+impl<T: DataType> RemoteStorage<T> {
+    pub fn new(client: Client) -> Self {
+        RemoteStorage {
+            client,
+            notifier: Notifier::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn hash(key: &str) -> Result<Hash> {
+        key.parse().map_err(|_| anyhow!(DataStoreError::Invalid))
     }
 }
 
 #[async_trait]
-impl<T: DataType> DataStore<T> for RemoteStorage {
+impl<T: DataType> DataStore<T> for RemoteStorage<T> {
     async fn read(&self, key: &str) -> Result<T> {
-        todo!()
+        let hash = Self::hash(key)?;
+        let mut client = self.client.clone();
+        let providers = client.get_providers(hash.clone()).await;
+        if providers.is_empty() {
+            bail!(DataStoreError::NotFound);
+        }
+        debug!("Fetching {hash:?} from {} providers", providers.len());
+        let block = client.request_block(hash, Some(providers)).await?;
+        T::deserialize(&block.serialize())
     }
 
+    /// Only announces the value, keeping none of its bytes, so the models never place values here.
     async fn write(&mut self, key: &str, value: &T) -> Result<()> {
-        todo!()
+        self.client.start_providing(Self::hash(key)?).await;
+        self.notifier.notify(Change::Written(key.to_owned()));
+        Ok(())
     }
 
     async fn delete(&mut self, key: &str) -> Result<()> {
-        todo!()
+        self.client.stop_providing(Self::hash(key)?).await;
+        self.notifier.notify(Change::Deleted(key.to_owned()));
+        Ok(())
+    }
+
+    /// Provider records expire on their own, as the DHT republishes them, so expiry is left to
+    /// the network.
+    async fn expire(&mut self, _key: &str, _expires: Option<Expiry>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn expiry(&self, _key: &str) -> Result<Option<Expiry>> {
+        Ok(None)
+    }
+
+    async fn sweep(&mut self) -> Result<Vec<(String, T)>> {
+        Ok(Vec::new())
+    }
+
+    async fn contains(&self, key: &str) -> Result<bool> {
+        let hash = Self::hash(key)?;
+        Ok(!self.client.clone().get_providers(hash).await.is_empty())
+    }
+
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        Ok(())
     }
 
-    async fn list(&self, key: Option<&str>) -> Result<Vec<&str>> {
-        todo!()
+    async fn on_change(&self) -> Result<Changes> {
+        Ok(self.notifier.subscribe())
     }
 }
@@ -0,0 +1,520 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use hashbrown::HashMap;
+use memmap2::MmapMut;
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    marker::PhantomData,
+    ops::Range,
+    os::fd::AsRawFd,
+    path::PathBuf,
+    sync::Mutex,
+};
+use tracing::{debug, warn};
+
+use crate::storage::{
+    Change, Changes, Codec, DataStoreError, DataType, Expiry, Filter, ListOptions, Notifier,
+};
+
+use super::{DataStore, Eviction};
+
+const MAGIC: &[u8; 8] = b"GRA-SHM\x04";
+
+/// Header layout: magic, the offset of the end of the last record, then the number of times the
+/// records have been compacted, i.e. moved.
+const TAIL: Range<usize> = 8..16;
+const COMPACTIONS: Range<usize> = 16..24;
+const HEADER_LEN: usize = 24;
+
+/// Record layout: liveness flag, key length, data length, expiry, then the key and the encoded
+/// data.
+const RECORD_HEADER_LEN: usize = 1 + 2 + 4 + 8;
+const EXPIRES: Range<usize> = 7..15;
+const LIVE: u8 = 1;
+const DEAD: u8 = 0;
+
+/// An advisory lock over the backing file, shared between every process mapping it.
+struct Lock<'a>(&'a File);
+
+impl<'a> Lock<'a> {
+    fn shared(file: &'a File) -> io::Result<Self> {
+        Self::acquire(file, libc::LOCK_SH)
+    }
+
+    fn exclusive(file: &'a File) -> io::Result<Self> {
+        Self::acquire(file, libc::LOCK_EX)
+    }
+
+    fn acquire(file: &'a File, operation: i32) -> io::Result<Self> {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Lock(file))
+    }
+}
+
+impl Drop for Lock<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// A record within the mapped region.
+#[derive(Debug)]
+struct Record {
+    offset: usize,
+    live: bool,
+    expires: Option<Expiry>,
+    key: Range<usize>,
+    data: Range<usize>,
+}
+
+impl Record {
+    fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires.is_past())
+    }
+
+    fn end(&self) -> usize {
+        self.data.end
+    }
+
+    fn len(&self) -> usize {
+        self.data.end - self.offset
+    }
+}
+
+/// Where each key was last seen, as of a tail and a number of compactions.
+///
+/// Other processes only append, flip flags in place, or compact, so the index is caught up by
+/// reading the records past its tail, checked against the flags at lookup, and rebuilt after a
+/// compaction.
+#[derive(Debug)]
+struct Index {
+    compactions: u64,
+    tail: usize,
+    offsets: HashMap<String, usize>,
+}
+
+impl Index {
+    fn new(compactions: u64) -> Self {
+        Index {
+            compactions,
+            tail: HEADER_LEN,
+            offsets: HashMap::new(),
+        }
+    }
+}
+
+/// A store shared between processes on the same host, through a memory-mapped file in /dev/shm.
+///
+/// Records are appended to the mapping, and when it fills up, the oldest records are evicted as
+/// the remainder is compacted towards the front, unless eviction is set to `Reject`. Only changes made through this instance are
+/// notified, not those made by other processes.
+#[derive(Debug)]
+pub struct ShmStorage<T: DataType> {
+    path: PathBuf,
+    file: File,
+    map: MmapMut,
+    codec: Codec,
+    eviction: Eviction,
+    index: Mutex<Index>,
+    notifier: Notifier,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DataType> ShmStorage<T> {
+    /// Opens, or creates, the shared file. An existing file keeps the size it was created with,
+    /// as other processes may have it mapped, and one not holding a store fails with `Invalid`.
+    pub fn new(path: PathBuf, max_size: usize) -> Result<Self> {
+        if max_size <= HEADER_LEN {
+            bail!(DataStoreError::Invalid);
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)?;
+
+        let lock = Lock::exclusive(&file)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            file.set_len(max_size as u64)?;
+        }
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        if len == 0 {
+            debug!("Initialising shared memory at {path:?}");
+            map[..MAGIC.len()].copy_from_slice(MAGIC);
+            Self::set_tail(&mut map, HEADER_LEN);
+            map[COMPACTIONS].copy_from_slice(&0u64.to_le_bytes());
+        } else if len <= HEADER_LEN || &map[..MAGIC.len()] != MAGIC {
+            bail!(DataStoreError::Invalid);
+        } else if len != max_size {
+            debug!("Adopting the {len} bytes of shared memory at {path:?}");
+        }
+        let index = Index::new(Self::compactions(&map));
+        drop(lock);
+
+        Ok(ShmStorage {
+            path,
+            file,
+            map,
+            codec: Codec::default(),
+            eviction: Eviction::Fifo,
+            index: Mutex::new(index),
+            notifier: Notifier::default(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Compresses values written from now on with the given codec.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Makes room with the given policy from now on. Records are only ever compacted in the
+    /// order they were written, so least recently used eviction is refused with `Invalid`.
+    pub fn with_eviction(mut self, eviction: Eviction) -> Result<Self> {
+        if eviction == Eviction::Lru {
+            bail!(DataStoreError::Invalid);
+        }
+        self.eviction = eviction;
+        Ok(self)
+    }
+
+    /// The default location for the given namespace, e.g. `/dev/shm/gra-blocks`.
+    pub fn default_path() -> PathBuf {
+        PathBuf::from("/dev/shm").join(format!("gra-{}", T::NAMESPACE))
+    }
+
+    fn tail(map: &[u8]) -> usize {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&map[TAIL]);
+        u64::from_le_bytes(bytes) as usize
+    }
+
+    fn set_tail(map: &mut [u8], tail: usize) {
+        map[TAIL].copy_from_slice(&(tail as u64).to_le_bytes());
+    }
+
+    fn compactions(map: &[u8]) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&map[COMPACTIONS]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn records(map: &[u8]) -> Vec<Record> {
+        Self::records_from(map, HEADER_LEN)
+    }
+
+    /// The records from the given offset, which must be the start of one, to the tail.
+    fn records_from(map: &[u8], start: usize) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut offset = start;
+        while let Some(record) = Self::record_at(map, offset) {
+            offset = record.end();
+            records.push(record);
+        }
+        records
+    }
+
+    /// The record starting at the given offset, unless it runs past the tail.
+    fn record_at(map: &[u8], offset: usize) -> Option<Record> {
+        let tail = Self::tail(map).min(map.len());
+        if offset + RECORD_HEADER_LEN > tail {
+            return None;
+        }
+        let key_len = u16::from_le_bytes([map[offset + 1], map[offset + 2]]) as usize;
+        let data_len = u32::from_le_bytes([
+            map[offset + 3],
+            map[offset + 4],
+            map[offset + 5],
+            map[offset + 6],
+        ]) as usize;
+        let key = offset + RECORD_HEADER_LEN..offset + RECORD_HEADER_LEN + key_len;
+        let data = key.end..key.end + data_len;
+        if data.end > tail {
+            return None;
+        }
+        let mut expires = [0; 8];
+        expires.copy_from_slice(&map[offset + EXPIRES.start..offset + EXPIRES.end]);
+        Some(Record {
+            offset,
+            live: map[offset] == LIVE,
+            expires: Expiry::from_secs(u64::from_le_bytes(expires)),
+            key,
+            data,
+        })
+    }
+
+    /// The live record of the key, looked up in the index, after catching it up with the map.
+    ///
+    /// Must be called with the file locked.
+    fn find(index: &Mutex<Index>, map: &[u8], key: &str) -> Option<Record> {
+        let mut index = index.lock().expect("index lock");
+        let (compactions, tail) = (Self::compactions(map), Self::tail(map));
+        if index.compactions != compactions || index.tail > tail {
+            *index = Index::new(compactions);
+        }
+        for record in Self::records_from(map, index.tail) {
+            index.tail = record.end();
+            if record.live {
+                let key = String::from_utf8_lossy(&map[record.key.clone()]).to_string();
+                index.offsets.insert(key, record.offset);
+            }
+        }
+
+        let offset = *index.offsets.get(key)?;
+        Self::record_at(map, offset)
+            .filter(|record| record.live && &map[record.key.clone()] == key.as_bytes())
+    }
+
+    /// Drops dead records, the one at `replaced`, if any, and the oldest live ones, until
+    /// `needed` bytes are free at the tail.
+    ///
+    /// Which records go, and what the evicted ones hold, is settled before any bytes are moved,
+    /// so the map is never left half compacted. Evicted records which can't be decoded are
+    /// dropped, rather than returned.
+    fn compact(
+        map: &mut [u8],
+        needed: usize,
+        replaced: Option<usize>,
+    ) -> Vec<(String, T, Option<Expiry>)> {
+        let kept = |record: &Record| record.live && Some(record.offset) != replaced;
+        let records = Self::records(map);
+        let mut live: usize = records.iter().filter(|r| kept(r)).map(Record::len).sum();
+        let mut evicted = Vec::new();
+        let mut moved = Vec::new();
+        for record in records.into_iter().filter(kept) {
+            if HEADER_LEN + live + needed <= map.len() {
+                moved.push(record);
+                continue;
+            }
+            live -= record.len();
+            let key = String::from_utf8_lossy(&map[record.key.clone()]).to_string();
+            match Codec::decode(&map[record.data.clone()]).and_then(|data| T::deserialize(&data)) {
+                Ok(value) => evicted.push((key, value, record.expires)),
+                Err(e) => warn!("Dropping {key:?}, which can't be decoded: {e}"),
+            }
+        }
+
+        let mut cursor = HEADER_LEN;
+        for record in moved {
+            map.copy_within(record.offset..record.end(), cursor);
+            cursor += record.len();
+        }
+        Self::set_tail(map, cursor);
+        let compactions = Self::compactions(map).wrapping_add(1);
+        map[COMPACTIONS].copy_from_slice(&compactions.to_le_bytes());
+        evicted
+    }
+}
+
+#[async_trait]
+impl<T: DataType> DataStore<T> for ShmStorage<T> {
+    async fn write(&mut self, key: &str, value: &T) -> Result<()> {
+        self.insert(key, value, None).await.map(|_| ())
+    }
+
+    async fn insert(
+        &mut self,
+        key: &str,
+        value: &T,
+        expires: Option<Expiry>,
+    ) -> Result<Vec<(String, T, Option<Expiry>)>> {
+        let data = self.codec.encode(&value.serialize())?;
+        let len = RECORD_HEADER_LEN + key.len() + data.len();
+        if key.len() > u16::MAX as usize || HEADER_LEN + len > self.map.len() {
+            bail!(DataStoreError::TooLarge);
+        }
+
+        let _lock = Lock::exclusive(&self.file)?;
+        let map = &mut self.map[..];
+        let previous = Self::find(&self.index, map, key);
+        if self.eviction == Eviction::Reject && Self::tail(map) + len > map.len() {
+            // Only dead records may be dropped, so the value must fit beside every live one.
+            let live: usize = Self::records(map)
+                .iter()
+                .filter(|record| record.live)
+                .filter(|record| previous.as_ref().map(|p| p.offset) != Some(record.offset))
+                .map(Record::len)
+                .sum();
+            if HEADER_LEN + live + len > map.len() {
+                bail!(DataStoreError::NoSpace);
+            }
+        }
+
+        // The previous record stays live until the new one is written, unless a compaction
+        // making room for the new one, which can't fail, drops it first.
+        let mut previous = previous.map(|previous| previous.offset);
+        let mut evicted = Vec::new();
+        if Self::tail(map) + len > map.len() {
+            evicted = Self::compact(map, len, previous.take());
+        }
+
+        let offset = Self::tail(map);
+        map[offset] = LIVE;
+        map[offset + 1..offset + 3].copy_from_slice(&(key.len() as u16).to_le_bytes());
+        map[offset + 3..offset + 7].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        map[offset + EXPIRES.start..offset + EXPIRES.end]
+            .copy_from_slice(&Expiry::to_secs(expires).to_le_bytes());
+        let key_start = offset + RECORD_HEADER_LEN;
+        map[key_start..key_start + key.len()].copy_from_slice(key.as_bytes());
+        map[key_start + key.len()..offset + len].copy_from_slice(&data);
+        Self::set_tail(map, offset + len);
+        if let Some(previous) = previous {
+            map[previous] = DEAD;
+        }
+
+        for (evicted_key, _, _) in &evicted {
+            self.notifier
+                .notify(Change::Evicted(evicted_key.to_owned()));
+        }
+        self.notifier.notify(Change::Written(key.to_owned()));
+        Ok(evicted)
+    }
+
+    async fn read(&self, key: &str) -> Result<T> {
+        let _lock = Lock::shared(&self.file)?;
+        let record = Self::find(&self.index, &self.map, key)
+            .filter(|record| !record.is_expired())
+            .ok_or(DataStoreError::NotFound)?;
+        T::deserialize(&Codec::decode(&self.map[record.data])?)
+    }
+
+    async fn delete(&mut self, key: &str) -> Result<()> {
+        let _lock = Lock::exclusive(&self.file)?;
+        let record = Self::find(&self.index, &self.map, key).ok_or(DataStoreError::NotFound)?;
+        self.map[record.offset] = DEAD;
+        self.notifier.notify(Change::Deleted(key.to_owned()));
+        Ok(())
+    }
+
+    async fn expire(&mut self, key: &str, expires: Option<Expiry>) -> Result<()> {
+        let _lock = Lock::exclusive(&self.file)?;
+        let record = Self::find(&self.index, &self.map, key).ok_or(DataStoreError::NotFound)?;
+        self.map[record.offset + EXPIRES.start..record.offset + EXPIRES.end]
+            .copy_from_slice(&Expiry::to_secs(expires).to_le_bytes());
+        Ok(())
+    }
+
+    async fn expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        let _lock = Lock::shared(&self.file)?;
+        let record = Self::find(&self.index, &self.map, key).ok_or(DataStoreError::NotFound)?;
+        Ok(record.expires)
+    }
+
+    async fn sweep(&mut self) -> Result<Vec<(String, T)>> {
+        let _lock = Lock::exclusive(&self.file)?;
+        let mut swept = Vec::new();
+        for record in Self::records(&self.map)
+            .into_iter()
+            .filter(|record| record.live && record.is_expired())
+        {
+            let key = String::from_utf8_lossy(&self.map[record.key.clone()]).to_string();
+            let value = T::deserialize(&Codec::decode(&self.map[record.data.clone()])?)?;
+            self.map[record.offset] = DEAD;
+            self.notifier.notify(Change::Expired(key.to_owned()));
+            swept.push((key, value));
+        }
+        Ok(swept)
+    }
+
+    async fn list(&self, options: &ListOptions) -> Result<Vec<String>> {
+        let _lock = Lock::shared(&self.file)?;
+        Ok(options.page(
+            Self::records(&self.map)
+                .iter()
+                .filter(|r| r.live)
+                .map(|r| String::from_utf8_lossy(&self.map[r.key.clone()]).to_string()),
+        ))
+    }
+
+    async fn contains(&self, key: &str) -> Result<bool> {
+        let _lock = Lock::shared(&self.file)?;
+        Ok(Self::find(&self.index, &self.map, key).is_some_and(|record| !record.is_expired()))
+    }
+
+    async fn len(&self) -> Result<usize> {
+        let _lock = Lock::shared(&self.file)?;
+        Ok(Self::records(&self.map).iter().filter(|r| r.live).count())
+    }
+
+    async fn size(&self) -> Result<u64> {
+        let _lock = Lock::shared(&self.file)?;
+        Ok(Self::records(&self.map)
+            .iter()
+            .filter(|r| r.live)
+            .map(|r| r.len() as u64)
+            .sum())
+    }
+
+    async fn extend_filter(&self, filter: &mut Filter) -> Result<()> {
+        let _lock = Lock::shared(&self.file)?;
+        Self::records(&self.map)
+            .iter()
+            .filter(|r| r.live)
+            .for_each(|r| filter.insert(&String::from_utf8_lossy(&self.map[r.key.clone()])));
+        Ok(())
+    }
+
+    async fn on_change(&self) -> Result<Changes> {
+        Ok(self.notifier.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Block;
+
+    fn open(name: &str, records: usize) -> ShmStorage<Block> {
+        let path = std::env::temp_dir().join(format!("gra-shm-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // Sized to hold `records` records of `value`, under one byte keys, and no more.
+        let len = RECORD_HEADER_LEN
+            + 1
+            + Codec::default()
+                .encode(&value(0).serialize())
+                .unwrap()
+                .len();
+        ShmStorage::new(path, HEADER_LEN + records * len).unwrap()
+    }
+
+    fn value(byte: u8) -> Block {
+        Block::Bytes(vec![[byte; 32]])
+    }
+
+    #[tokio::test]
+    async fn overwrite_through_compaction_keeps_the_new_value() {
+        let mut store = open("overwrite", 2);
+        store.insert("a", &value(1), None).await.unwrap();
+        store.insert("b", &value(2), None).await.unwrap();
+        assert!(store.insert("a", &value(3), None).await.unwrap().is_empty());
+        assert_eq!(store.read("a").await.unwrap(), value(3));
+        assert_eq!(store.read("b").await.unwrap(), value(2));
+        assert_eq!(store.len().await.unwrap(), 2);
+
+        // Without a compaction, the previous record is dropped once the new one is written.
+        let mut store = open("in-place", 3);
+        store.insert("a", &value(1), None).await.unwrap();
+        store.insert("a", &value(2), None).await.unwrap();
+        assert_eq!(store.read("a").await.unwrap(), value(2));
+        assert_eq!(store.len().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn compaction_drops_undecodable_records() {
+        let mut store = open("undecodable", 2);
+        store.insert("a", &value(1), None).await.unwrap();
+        store.insert("b", &value(2), None).await.unwrap();
+        let record = ShmStorage::<Block>::records(&store.map).remove(0);
+        store.map[record.data.start] = 0xff;
+
+        let evicted = store.insert("c", &value(3), None).await.unwrap();
+        assert!(evicted.is_empty());
+        assert_eq!(store.read("b").await.unwrap(), value(2));
+        assert_eq!(store.read("c").await.unwrap(), value(3));
+    }
+}